@@ -0,0 +1,551 @@
+//! Host simulator of the engine control state machine.
+//!
+//! Runs the state handler decisions and the pyro sequence of the firmware, see
+//! `pike_enginecontrol::machine`, against the pyro controller of the board with mock pins, from a
+//! script of timestamped commands and sensor samples. The firmware tasks around them are mirrored
+//! here: an event is handled as soon as it is posted, as the state handler preempts the task
+//! raising it, and the pyro steps wait in the queue of the pyro handler until the tasks of the
+//! current tick have run. Prints a trace of the journaled transitions and of every change of the
+//! system state, the pyro state and the pin levels.
+//!
+//! ```sh
+//! cargo run --example simulator --target x86_64-unknown-linux-gnu -- examples/scripts/nominal.sim
+//! ```
+//!
+//! The traces of the scripts in `examples/scripts` are kept next to them as `<script>.trace` and
+//! checked by `cargo test --example simulator --target x86_64-unknown-linux-gnu`. After an
+//! intended change of the behaviour they are regenerated by redirecting the output of the
+//! simulator into them.
+//!
+//! Script lines are `<time ms> <command> [argument]`, in chronological order, `#` starts a
+//! comment:
+//!
+//! - `request <STATE>`: state request, e.g. from the flight computer
+//! - `continuity <pyro1|ignition|pyro2|all> <ok|open>`: continuity sense input of the channel, the
+//!   capacitor charge is timed as on the board
+//! - `acceleration <g>`, `altitude <m>`: flight data received over CAN
+//! - `fault <charge|stuck>`: injects a fault
+//! - `end`: stops the simulation
+//!
+//! The pyro steps still queued when the script ends are run before the simulation stops.
+
+use std::{cell::RefCell, collections::BTreeMap, collections::VecDeque, fs, process, rc::Rc};
+
+use embedded_hal::digital::v2::OutputPin;
+use pike_enginecontrol::{
+    aero::FlightData,
+    board::{self, PYRO_CHANNELS, PYRO_CHANNEL_NAMES},
+    burnout::BurnoutDetector,
+    charge::{ChargeMonitor, ChargeStatus},
+    config::{
+        BURNOUT, CHARGE, DWELL_LIMITS, HANGFIRE_LOCKOUT_MS, LIFTOFF, MONITOR_PERIOD_MS,
+        TRANSITION_STEP_TIMEOUT_MS,
+    },
+    continuity::Continuity,
+    engine::{max_dwell_ms, Conditions, Trigger},
+    event::{Event, Fault, PyroEvent, StateEvent},
+    journal::{Journal, JournalResult, JournalTransition},
+    liftoff::LiftoffDetector,
+    machine::{self, MachineRuntime},
+    pin::Output,
+    pyro::{FirePulse, PyroChannelName, PyroController, PyroState},
+    sequence::{PyroSequence, SequenceRuntime},
+    StateEnum,
+};
+
+// Resolution of the simulation clock
+const TICK_MS: u32 = 10;
+
+// Capacity of the pyro handler task in `main.rs`
+const PYRO_HANDLER_CAPACITY: usize = 5;
+
+/// Name of the channel's pin in the trace and in the script.
+fn pin_name(channel: PyroChannelName) -> &'static str {
+    match channel {
+        PyroChannelName::Pyro1 => "pyro1",
+        PyroChannelName::Pyro2 => "pyro2",
+        PyroChannelName::Ignition => "ignition",
+        PyroChannelName::Any => "any",
+    }
+}
+
+type Levels = Rc<RefCell<BTreeMap<&'static str, bool>>>;
+
+/// Output pin that keeps its level in a map shared by all pins.
+struct MockPin {
+    name: &'static str,
+    levels: Levels,
+}
+
+impl OutputPin for MockPin {
+    type Error = ();
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.levels.borrow_mut().insert(self.name, false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.levels.borrow_mut().insert(self.name, true);
+        Ok(())
+    }
+}
+
+fn pin<const INVERTED: bool>(name: &'static str, levels: &Levels) -> Output<MockPin, INVERTED> {
+    levels.borrow_mut().insert(name, false);
+    Output::new(MockPin {
+        name,
+        levels: levels.clone(),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    Request(StateEnum),
+    Continuity(Option<PyroChannelName>, Continuity),
+    Flight(FlightData),
+    Fault(Fault),
+    End,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Timer {
+    Dwell(StateEnum),
+    PulseEnd(FirePulse),
+    Watchdog,
+    Poll,
+    LockoutEnd,
+}
+
+/// What the state machine acts on, see `HandlerRuntime` of the state task. The steps handed to the
+/// pyro handler are queued, see [`Simulator::run_pyro_handler`].
+struct Runtime {
+    now: u32,
+    state: StateEnum,
+    controller: PyroController<PYRO_CHANNELS, MockPin>,
+    timers: Vec<(u32, Timer)>,
+    steps: VecDeque<(PyroState, Option<Trigger>)>,
+    journal: Journal<64>,
+    output: Vec<String>,
+}
+
+impl Runtime {
+    fn schedule(&mut self, after_ms: u32, timer: Timer) {
+        self.timers.push((self.now + after_ms, timer));
+    }
+
+    fn cancel(&mut self, timer: fn(&Timer) -> bool) {
+        self.timers.retain(|(_, pending)| !timer(pending));
+    }
+}
+
+impl SequenceRuntime for Runtime {
+    fn now_ms(&self) -> u32 {
+        self.now
+    }
+
+    fn spawn_step(&mut self, step: PyroState, trigger: Option<Trigger>) -> bool {
+        if self.steps.len() == PYRO_HANDLER_CAPACITY {
+            return false;
+        }
+        self.steps.push_back((step, trigger));
+        true
+    }
+
+    fn arm_watchdog(&mut self) {
+        self.cancel_watchdog();
+        self.schedule(TRANSITION_STEP_TIMEOUT_MS, Timer::Watchdog);
+    }
+
+    fn cancel_watchdog(&mut self) {
+        self.cancel(|timer| *timer == Timer::Watchdog);
+    }
+
+    fn schedule_poll(&mut self, after_ms: u32) {
+        self.cancel_poll();
+        self.schedule(after_ms, Timer::Poll);
+    }
+
+    fn cancel_poll(&mut self) {
+        self.cancel(|timer| *timer == Timer::Poll);
+    }
+}
+
+impl MachineRuntime for Runtime {
+    fn state(&self) -> StateEnum {
+        self.state
+    }
+
+    fn enter(&mut self, state: StateEnum) {
+        self.state = state;
+        self.cancel(|timer| matches!(timer, Timer::Dwell(_)));
+        if let Some(max_ms) = max_dwell_ms(DWELL_LIMITS, state) {
+            self.schedule(max_ms, Timer::Dwell(state));
+        }
+    }
+
+    fn record(
+        &mut self,
+        transition: JournalTransition,
+        trigger: Option<Trigger>,
+        result: JournalResult,
+    ) {
+        let entry = self.journal.record(self.now, transition, trigger, result);
+        self.output.push(entry.to_string());
+    }
+
+    fn abort_pyro(&mut self) {
+        self.controller.safe_state();
+    }
+
+    fn start_lockout(&mut self) {
+        self.cancel(|timer| *timer == Timer::LockoutEnd);
+        self.schedule(HANGFIRE_LOCKOUT_MS, Timer::LockoutEnd);
+    }
+
+    // Reports go out over CAN, they are not traced
+    fn report(&mut self, _event: &Event) {}
+}
+
+struct Simulator {
+    runtime: Runtime,
+    sequence: PyroSequence,
+    conditions: Conditions,
+    levels: Levels,
+    charge_monitor: ChargeMonitor,
+    burnout_detector: BurnoutDetector,
+    liftoff_detector: LiftoffDetector,
+    sense: [Continuity; PYRO_CHANNELS],
+    trace: Option<String>,
+}
+
+impl Simulator {
+    fn new() -> Self {
+        let levels = Levels::default();
+        let controller = board::pyro_controller(
+            pin("charge", &levels),
+            pin("discharge", &levels),
+            PYRO_CHANNEL_NAMES.map(|name| (name, pin(pin_name(name), &levels))),
+        )
+        .unwrap();
+        Self {
+            runtime: Runtime {
+                now: 0,
+                state: StateEnum::IDLE,
+                controller,
+                timers: Vec::new(),
+                steps: VecDeque::new(),
+                journal: Journal::new(),
+                output: Vec::new(),
+            },
+            sequence: PyroSequence::new(),
+            conditions: Conditions::default(),
+            levels,
+            charge_monitor: ChargeMonitor::new(CHARGE),
+            burnout_detector: BurnoutDetector::new(BURNOUT),
+            liftoff_detector: LiftoffDetector::new(LIFTOFF),
+            // Open channels until the script says otherwise
+            sense: [Continuity::Open; PYRO_CHANNELS],
+            trace: None,
+        }
+    }
+
+    /// Runs the script, returns the trace once the `end` command or the end of the script is
+    /// reached.
+    fn run(mut self, script: &[(u32, Command)]) -> Vec<String> {
+        let mut script = script.iter().peekable();
+        self.print_trace();
+        loop {
+            let now = self.runtime.now;
+            while let Some((_, command)) = script.next_if(|(at, _)| *at <= now) {
+                if *command == Command::End {
+                    return self.finish();
+                }
+                self.apply(*command);
+            }
+            if script.peek().is_none() {
+                return self.finish();
+            }
+
+            if now % MONITOR_PERIOD_MS == 0 {
+                self.monitor();
+            }
+            self.expire_timers();
+            self.run_pyro_handler();
+            self.print_trace();
+            self.runtime.now += TICK_MS;
+        }
+    }
+
+    /// Runs the pyro steps still queued, so that the trace ends with the outcome of the last
+    /// commands.
+    fn finish(mut self) -> Vec<String> {
+        self.run_pyro_handler();
+        self.print_trace();
+        self.runtime.output
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Request(state) => self.handle(None, Some(state)),
+            Command::Continuity(channel, continuity) => {
+                for (index, name) in PYRO_CHANNEL_NAMES.iter().enumerate() {
+                    if channel.map_or(true, |channel| channel == *name) {
+                        self.sense[index] = continuity;
+                    }
+                }
+            }
+            Command::Flight(data) => self.flight_data(data),
+            Command::Fault(fault) => self.post(Event::Fault(fault)),
+            Command::End => {}
+        }
+    }
+
+    /// Mirrors the pyro monitor task.
+    fn monitor(&mut self) {
+        let armed = matches!(self.runtime.state, StateEnum::READY | StateEnum::IGNITION);
+        for (index, name) in PYRO_CHANNEL_NAMES.iter().enumerate() {
+            let changed = self
+                .runtime
+                .controller
+                .set_continuity(*name, self.sense[index]);
+            if let (Some(continuity), true) = (changed, armed) {
+                self.post(Event::StateInfo(StateEvent::ContinuityChanged(
+                    *name, continuity,
+                )));
+            }
+        }
+        self.conditions.continuity_ok = self.runtime.controller.continuity_ok();
+
+        if self.runtime.controller.get_state() == PyroState::CHARGING {
+            if let Some(ChargeStatus::Charged) = self.charge_monitor.update_timed(self.runtime.now)
+            {
+                self.post(Event::StateInfo(StateEvent::ChargeComplete));
+            }
+        } else {
+            self.charge_monitor.reset();
+        }
+    }
+
+    /// Mirrors the flight monitor task.
+    fn flight_data(&mut self, data: FlightData) {
+        let now = self.runtime.now;
+        let event = match (self.runtime.state, data) {
+            (StateEnum::IGNITION, _) => {
+                self.burnout_detector.reset();
+                self.liftoff_detector
+                    .update(now, data)
+                    .then(|| StateEvent::Liftoff)
+            }
+            (StateEnum::PROPULSION, FlightData::Acceleration(acceleration)) => {
+                self.liftoff_detector.reset();
+                self.burnout_detector
+                    .update(now, acceleration)
+                    .then(|| StateEvent::Burnout)
+            }
+            (StateEnum::PROPULSION, _) => None,
+            _ => {
+                self.liftoff_detector.reset();
+                self.burnout_detector.reset();
+                None
+            }
+        };
+        if let Some(event) = event {
+            self.post(Event::StateInfo(event));
+        }
+    }
+
+    fn expire_timers(&mut self) {
+        let now = self.runtime.now;
+        let (expired, pending) = self
+            .runtime
+            .timers
+            .drain(..)
+            .partition(|(due, _)| *due <= now);
+        self.runtime.timers = pending;
+        for (_, timer) in expired {
+            match timer {
+                Timer::Dwell(state) => self.post(Event::StateInfo(StateEvent::Timeout(state))),
+                Timer::PulseEnd(pulse) => match self.runtime.controller.end_pulse(pulse) {
+                    Ok(true) => self.post(Event::Pyro(PyroEvent::PulseEnded(pulse.channel))),
+                    Ok(false) => {}
+                    Err(e) => self.post(Event::Fault(Fault::Pyro(e))),
+                },
+                Timer::Watchdog => self.post(Event::Fault(Fault::TransitionStuck)),
+                Timer::Poll => self.handle(None, None),
+                Timer::LockoutEnd => self.conditions.locked_out = false,
+            }
+        }
+    }
+
+    /// Mirrors the pyro handler task, runs the queued steps in order. The event of a step is
+    /// handled right away and may queue the next step.
+    fn run_pyro_handler(&mut self) {
+        while let Some((step, trigger)) = self.runtime.steps.pop_front() {
+            let runtime = &mut self.runtime;
+            let result = runtime.controller.change_state(step);
+            let outcome = match result {
+                Ok(_) => JournalResult::Done,
+                Err(_) => JournalResult::Failed,
+            };
+            runtime.record(JournalTransition::Pyro(step), trigger, outcome);
+            if let (Ok(true), Some(pulse)) = (result, runtime.controller.active_pulse()) {
+                runtime.schedule(pulse.width_ms, Timer::PulseEnd(pulse));
+            }
+            self.post(machine::step_event(step, result));
+        }
+    }
+
+    /// Mirrors `post_event`: the state handler takes the event at once.
+    fn post(&mut self, event: Event) {
+        self.handle(Some(event), None);
+    }
+
+    fn handle(&mut self, event: Option<Event>, new_state: Option<StateEnum>) {
+        machine::process(
+            &mut self.runtime,
+            &mut self.sequence,
+            &mut self.conditions,
+            event,
+            new_state,
+        );
+    }
+
+    /// Traces the system state, the pyro state and the pin levels if any of them has changed.
+    fn print_trace(&mut self) {
+        let pins: Vec<String> = self
+            .levels
+            .borrow()
+            .iter()
+            .map(|(name, high)| format!("{}={}", name, *high as u8))
+            .collect();
+        let trace = format!(
+            "{:?} pyro {:?} [{}]",
+            self.runtime.state,
+            self.runtime.controller.get_state(),
+            pins.join(" ")
+        );
+        if self.trace.as_ref() != Some(&trace) {
+            let line = format!("{:>10} ms {}", self.runtime.now, trace);
+            self.runtime.output.push(line);
+            self.trace = Some(trace);
+        }
+    }
+}
+
+fn parse_state(name: &str) -> Option<StateEnum> {
+    Some(match name {
+        "IDLE" => StateEnum::IDLE,
+        "READY" => StateEnum::READY,
+        "IGNITION" => StateEnum::IGNITION,
+        "PROPULSION" => StateEnum::PROPULSION,
+        "BURNOUT" => StateEnum::BURNOUT,
+        "ABORT" => StateEnum::ABORT,
+        _ => return None,
+    })
+}
+
+fn parse_line(line: &str) -> Result<Option<(u32, Command)>, String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let at = words[0]
+        .parse()
+        .map_err(|_| format!("invalid time `{}`", words[0]))?;
+    let argument = |index: usize| {
+        words
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("`{}` is missing an argument", words[1]))
+    };
+    let real = |word: &str| -> Result<f32, String> {
+        word.parse()
+            .map_err(|_| format!("invalid number `{}`", word))
+    };
+
+    let command = match *words.get(1).ok_or("missing command")? {
+        "request" => {
+            let name = argument(2)?;
+            Command::Request(parse_state(name).ok_or(format!("unknown state `{}`", name))?)
+        }
+        "continuity" => {
+            let channel = match argument(2)? {
+                "all" => None,
+                name => Some(
+                    PYRO_CHANNEL_NAMES
+                        .iter()
+                        .copied()
+                        .find(|channel| pin_name(*channel) == name)
+                        .ok_or(format!("unknown channel `{}`", name))?,
+                ),
+            };
+            let continuity = match argument(3)? {
+                "ok" => Continuity::Ok,
+                "open" => Continuity::Open,
+                level => return Err(format!("unknown continuity `{}`", level)),
+            };
+            Command::Continuity(channel, continuity)
+        }
+        "acceleration" => Command::Flight(FlightData::Acceleration(real(argument(2)?)?)),
+        "altitude" => Command::Flight(FlightData::Altitude(real(argument(2)?)?)),
+        "fault" => match argument(2)? {
+            "charge" => Command::Fault(Fault::ChargeFailure),
+            "stuck" => Command::Fault(Fault::TransitionStuck),
+            name => return Err(format!("unknown fault `{}`", name)),
+        },
+        "end" => Command::End,
+        name => return Err(format!("unknown command `{}`", name)),
+    };
+    Ok(Some((at, command)))
+}
+
+/// Reads and parses the script at `path`.
+fn load_script(path: &str) -> Result<Vec<(u32, Command)>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut script = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        if let Some(entry) =
+            parse_line(line).map_err(|e| format!("{}:{}: {}", path, number + 1, e))?
+        {
+            script.push(entry);
+        }
+    }
+    if script.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+        return Err(format!("{}: lines are not in chronological order", path));
+    }
+    Ok(script)
+}
+
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: simulator <script>");
+        process::exit(2);
+    });
+    let script = load_script(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    for line in Simulator::new().run(&script) {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The traces of the example scripts are reviewed, any change of the behaviour shows up here.
+    #[test]
+    fn scripts_match_expected_traces() {
+        for name in ["nominal", "hangfire"] {
+            let path = format!("{}/examples/scripts/{}", env!("CARGO_MANIFEST_DIR"), name);
+            let script = load_script(&format!("{}.sim", path)).unwrap();
+            let expected = fs::read_to_string(format!("{}.trace", path)).unwrap();
+            let trace: Vec<String> = Simulator::new().run(&script);
+            assert_eq!(trace, expected.lines().collect::<Vec<_>>(), "{}", name);
+        }
+    }
+}
@@ -3,4 +3,12 @@ MEMORY
 {
   FLASH : ORIGIN = 0x08000000, LENGTH = 64K
   RAM : ORIGIN = 0x20000000, LENGTH = 20K
-}
\ No newline at end of file
+}
+/* The panic record of src/panic.rs, not touched by the reset handler so that it survives a reset */
+SECTIONS
+{
+  .panic_record (NOLOAD) : ALIGN(4)
+  {
+    KEEP(*(.panic_record));
+  } > RAM
+} INSERT AFTER .bss;
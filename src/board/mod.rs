@@ -0,0 +1,20 @@
+//! Board description of the Pike engine control hardware. The revision is selected with a cargo
+//! feature, everything wired differently between revisions lives in its module. The pyro channels
+//! and groups of a revision are described by `pike_enginecontrol::board`, which the simulator
+//! shares.
+
+use pike_enginecontrol::{board, pin::ErasedPP, pyro::PyroError};
+
+pub use board::{PYRO_CHANNELS, PYRO_CHANNEL_NAMES};
+
+#[cfg(feature = "pike-rev1")]
+mod pike_rev1;
+#[cfg(feature = "pike-rev1")]
+pub use pike_rev1::*;
+
+pub type PyroController = pike_enginecontrol::pyro::PyroController<PYRO_CHANNELS, ErasedPP>;
+
+/// Builds the pyro controller from the pins and the channel groups of the board.
+pub fn pyro_controller(pins: PyroPins) -> Result<PyroController, PyroError> {
+    board::pyro_controller(pins.charge, pins.discharge, pins.channels)
+}
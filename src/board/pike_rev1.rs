@@ -0,0 +1,164 @@
+//! Badger:Pike Engine Control, first revision.
+
+use embedded_hal::digital::v2::InputPin;
+use pike_enginecontrol::{
+    board::PYRO_CHANNELS,
+    continuity::Continuity,
+    pin::{Output, PINErasedPP, PINErasedPPInv},
+    pyro::PyroChannelName,
+};
+use stm32f1xx_hal::{
+    afio,
+    gpio::{
+        self,
+        gpioa::{self, PA11, PA12},
+        gpiob::{self, PB10, PB11, PB15, PB6, PB7, PB8},
+        gpioc::{self, PC14},
+        Alternate, Floating, Input, OpenDrain, PinState, PushPull,
+    },
+    pac,
+    prelude::*,
+};
+
+pub type LedHeartbeat = PC14<gpio::Output<PushPull>>;
+pub type LedCont = PB15<gpio::Output<PushPull>>;
+
+/// Outputs of the pyro circuit.
+pub struct PyroPins {
+    pub charge: PINErasedPP,
+    pub discharge: PINErasedPPInv,
+    pub channels: [(PyroChannelName, PINErasedPP); PYRO_CHANNELS],
+}
+
+/// Level of a continuity sense input while an e-match is connected.
+///
+/// The board pinout names the sense inputs but not their polarity, high is taken here. Check it
+/// with a known-good e-match before connecting a live one.
+const CONTINUITY_LEVEL: bool = true;
+
+/// Continuity sense inputs of the pyro channels: CONT0 (ignition) on PB8, CONT1 (pyro1) on PB7 and
+/// CONT2 (pyro2) on PB6, as given by the board pinout.
+///
+/// The inputs are digital, a sense input tells a connected e-match from an open channel but cannot
+/// detect a short. The board has no sense input for the firing capacitor voltage, the charge is
+/// timed instead, see [`ChargeConfig`](pike_enginecontrol::charge::ChargeConfig).
+pub struct SensePins {
+    pyro1: PB7<Input<Floating>>,
+    pyro2: PB6<Input<Floating>>,
+    ignition: PB8<Input<Floating>>,
+}
+
+impl SensePins {
+    pub fn read_channel(&self, channel: PyroChannelName) -> Option<Continuity> {
+        let level = match channel {
+            PyroChannelName::Pyro1 => InputPin::is_high(&self.pyro1),
+            PyroChannelName::Pyro2 => InputPin::is_high(&self.pyro2),
+            PyroChannelName::Ignition => InputPin::is_high(&self.ignition),
+            PyroChannelName::Any => return None,
+        };
+        if level.ok()? == CONTINUITY_LEVEL {
+            Some(Continuity::Ok)
+        } else {
+            Some(Continuity::Open)
+        }
+    }
+}
+
+pub struct Board {
+    pub can_rx: PA11<Input<Floating>>,
+    pub can_tx: PA12<Alternate<PushPull>>,
+    pub led_heartbeat: LedHeartbeat,
+    pub led_cont: LedCont,
+    pub i2c_scl: PB10<Alternate<OpenDrain>>,
+    pub i2c_sda: PB11<Alternate<OpenDrain>>,
+    pub pyro: PyroPins,
+    pub sense: SensePins,
+}
+
+impl Board {
+    /// Configures every pin of the board.
+    pub fn new(
+        mut gpioa: gpioa::Parts,
+        mut gpiob: gpiob::Parts,
+        mut gpioc: gpioc::Parts,
+        afio: &mut afio::Parts,
+    ) -> Self {
+        let (pa15, _, _) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
+
+        let pyro = PyroPins {
+            charge: Output::new(
+                gpioc
+                    .pc15
+                    .into_push_pull_output_with_state(&mut gpioc.crh, PinState::High)
+                    .erase(),
+            ),
+            discharge: Output::new(
+                gpiob
+                    .pb14
+                    .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low)
+                    .erase(),
+            ),
+            channels: [
+                (
+                    PyroChannelName::Pyro1,
+                    Output::new(
+                        gpiob
+                            .pb13
+                            .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low)
+                            .erase(),
+                    ),
+                ),
+                (
+                    PyroChannelName::Ignition,
+                    Output::new(
+                        gpioc
+                            .pc13
+                            .into_push_pull_output_with_state(&mut gpioc.crh, PinState::Low)
+                            .erase(),
+                    ),
+                ),
+                (
+                    PyroChannelName::Pyro2,
+                    Output::new(
+                        pa15.into_push_pull_output_with_state(&mut gpioa.crh, PinState::Low)
+                            .erase(),
+                    ),
+                ),
+            ],
+        };
+
+        Self {
+            can_rx: gpioa.pa11.into_floating_input(&mut gpioa.crh),
+            can_tx: gpioa.pa12.into_alternate_push_pull(&mut gpioa.crh),
+            led_heartbeat: gpioc
+                .pc14
+                .into_push_pull_output_with_state(&mut gpioc.crh, PinState::High),
+            led_cont: gpiob
+                .pb15
+                .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low),
+            i2c_scl: gpiob.pb10.into_alternate_open_drain(&mut gpiob.crh),
+            i2c_sda: gpiob.pb11.into_alternate_open_drain(&mut gpiob.crh),
+            pyro,
+            sense: SensePins {
+                pyro1: gpiob.pb7.into_floating_input(&mut gpiob.crl),
+                pyro2: gpiob.pb6.into_floating_input(&mut gpiob.crl),
+                ignition: gpiob.pb8.into_floating_input(&mut gpiob.crh),
+            },
+        }
+    }
+}
+
+/// Drives the pyro outputs into the safe configuration directly over the registers, without
+/// relying on the state of the HAL or the pyro controller.
+pub fn safe_pyro_outputs() {
+    unsafe {
+        // Channels off: PB13 (pyro1), PA15 (pyro2), PC13 (ignition)
+        (*pac::GPIOB::ptr()).bsrr.write(|w| w.br13().set_bit());
+        (*pac::GPIOA::ptr()).bsrr.write(|w| w.br15().set_bit());
+        (*pac::GPIOC::ptr()).bsrr.write(|w| w.br13().set_bit());
+        // Charge off: PC15
+        (*pac::GPIOC::ptr()).bsrr.write(|w| w.br15().set_bit());
+        // Discharge on: PB14, active low
+        (*pac::GPIOB::ptr()).bsrr.write(|w| w.br14().set_bit());
+    }
+}
@@ -1,31 +1,373 @@
-use bxcan::{Frame, Rx, Tx};
-use can_aerospace_lite::{driver::CANAerospaceDriver, message::CANAerospaceFrame};
+use bxcan::{
+    filter::{ListEntry16, MasterFilters},
+    Fifo, Frame, Id, OverrunError, Rx0, Rx1, StandardId, Tx,
+};
+use can_aerospace_lite::{
+    driver::CANAerospaceDriver,
+    message::{CANAerospaceFrame, CANAerospaceMessage},
+};
+use heapless::{
+    spsc::{Consumer, Producer, Queue},
+    Vec,
+};
 use stm32f1xx_hal::{can::Can, device::CAN1};
 
-pub struct CANDriver {
-    tx: Tx<Can<CAN1>>,
-    rx: Rx<Can<CAN1>>,
+use crate::{
+    aero::{BARO_ALTITUDE_ID, BODY_LONG_ACC_ID, NODE_ID, SERVICE_REQUEST_ID},
+    can_health::{CanHealth, ErrorStatus, LinkMonitor, TxErrorDecoder},
+};
+
+/// Identifiers of the data this node consumes, frames with identifiers that are neither here nor
+/// in [`PRIORITY_IDS`] are dropped by the acceptance filters.
+pub const DATA_IDS: &[u16] = &[BODY_LONG_ACC_ID, BARO_ALTITUDE_ID];
+
+/// Identifiers received through FIFO 1, so that they are never queued behind flight data: the node
+/// service requests carry the commands, including the abort. FIFO 1 is drained by its own
+/// interrupt, see [`RxFifo`].
+pub const PRIORITY_IDS: &[u16] = &[SERVICE_REQUEST_ID];
+
+/// Filter banks hold four identifiers each in 16 bit list mode.
+const IDS_PER_BANK: usize = 4;
+
+pub const LINK_QUEUE_LEN: usize = 8;
+pub type LinkQueue = Queue<Frame, LINK_QUEUE_LEN>;
+
+/// Frames waiting for a free mailbox.
+pub const TX_QUEUE_LEN: usize = 16;
+
+/// Slots of the transmit queue that bulk transfers leave free for the other messages.
+const TX_RESERVED: usize = 4;
+
+/// Last error code field of `CAN_ESR`.
+const ESR_LEC: u32 = 0b111 << 4;
+
+/// The receive FIFOs of bxcan, both are read the same way.
+pub trait FifoReceiver {
+    fn receive(&mut self) -> nb::Result<Frame, OverrunError>;
 }
 
-impl CANDriver {
-    pub fn new(tx: Tx<Can<CAN1>>, rx: Rx<Can<CAN1>>) -> Self {
-        Self { tx, rx }
+impl FifoReceiver for Rx0<Can<CAN1>> {
+    fn receive(&mut self) -> nb::Result<Frame, OverrunError> {
+        Rx0::receive(self)
     }
 }
 
-impl CANAerospaceDriver for CANDriver {
-    fn send_frame(&mut self, frame: can_aerospace_lite::message::CANAerospaceFrame) {
-        if self.tx.transmit(&Frame::from(&frame)).is_ok() {};
+impl FifoReceiver for Rx1<Can<CAN1>> {
+    fn receive(&mut self) -> nb::Result<Frame, OverrunError> {
+        Rx1::receive(self)
     }
+}
 
-    fn recv_frame(&mut self) -> Option<can_aerospace_lite::message::CANAerospaceFrame> {
+/// Receive FIFO of the CAN controller, each one is drained by its own interrupt.
+pub struct RxFifo<R> {
+    rx: R,
+    overruns: u32,
+}
+
+impl<R: FifoReceiver> RxFifo<R> {
+    pub fn new(rx: R) -> Self {
+        Self { rx, overruns: 0 }
+    }
+
+    /// Empties the FIFO. Messages handled by the firmware are passed to `on_message`, the other
+    /// frames to `on_other`.
+    pub fn receive(
+        &mut self,
+        mut on_message: impl FnMut(CANAerospaceMessage),
+        mut on_other: impl FnMut(Frame),
+    ) {
         loop {
             match self.rx.receive() {
-                Ok(frame) => return Some(CANAerospaceFrame::from(frame)),
+                Ok(frame) => match node_message(&frame) {
+                    Some(message) => on_message(message),
+                    None => on_other(frame),
+                },
                 Err(nb::Error::WouldBlock) => break,
-                Err(nb::Error::Other(_)) => continue, // Ignore overrun errors.
+                // A frame has been lost, the following ones are still valid
+                Err(nb::Error::Other(_)) => {
+                    self.overruns = self.overruns.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Frames lost since the last call.
+    pub fn take_overruns(&mut self) -> u32 {
+        core::mem::take(&mut self.overruns)
+    }
+}
+
+/// Owns the CAN peripheral and receive FIFO 0. Service requests addressed to this node and the
+/// sensor data the firmware uses are passed on as messages, every other frame is passed on to
+/// CANaerospace-Lite through an [`AeroLink`].
+pub struct CANDriver {
+    tx: Tx<Can<CAN1>>,
+    fifo0: RxFifo<Rx0<Can<CAN1>>>,
+    link_rx: Producer<'static, Frame, LINK_QUEUE_LEN>,
+    link_tx: Consumer<'static, Frame, LINK_QUEUE_LEN>,
+    /// Ordered by priority, frames of equal priority in the order they were queued
+    tx_queue: Vec<Frame, TX_QUEUE_LEN>,
+    tx_dropped: u32,
+    tx_errors: u32,
+    rx_overruns: u32,
+    error_status: ErrorStatus,
+    tx_error_decoder: TxErrorDecoder,
+    link: LinkMonitor,
+}
+
+/// CANaerospace-Lite side of the [`CANDriver`], frames are exchanged through queues so that the
+/// driver can be shared with the tasks sending their own messages.
+pub struct AeroLink {
+    rx: Consumer<'static, Frame, LINK_QUEUE_LEN>,
+    tx: Producer<'static, Frame, LINK_QUEUE_LEN>,
+}
+
+impl CANDriver {
+    /// Receive FIFO 1 is drained by its own interrupt, outside of the driver.
+    pub fn new(
+        tx: Tx<Can<CAN1>>,
+        fifo0: Rx0<Can<CAN1>>,
+        rx_queue: &'static mut LinkQueue,
+        tx_queue: &'static mut LinkQueue,
+    ) -> (Self, AeroLink) {
+        let (link_rx, rx) = rx_queue.split();
+        let (tx_link, link_tx) = tx_queue.split();
+        (
+            Self {
+                tx,
+                fifo0: RxFifo::new(fifo0),
+                link_rx,
+                link_tx,
+                tx_queue: Vec::new(),
+                tx_dropped: 0,
+                tx_errors: 0,
+                rx_overruns: 0,
+                error_status: ErrorStatus::default(),
+                tx_error_decoder: TxErrorDecoder::new(),
+                link: LinkMonitor::new(),
+            },
+            AeroLink { rx, tx: tx_link },
+        )
+    }
+
+    /// Queues the message for transmission. Returns `false` and counts the message as dropped if
+    /// the transmit queue is full.
+    pub fn transmit(&mut self, message: &CANAerospaceMessage) -> bool {
+        let queued = self.try_transmit(message);
+        if !queued {
+            self.tx_dropped = self.tx_dropped.wrapping_add(1);
+        }
+        queued
+    }
+
+    /// Queues the message for transmission, for callers that retry it themselves. Returns `false`
+    /// if the transmit queue is full, nothing is counted as dropped.
+    pub fn try_transmit(&mut self, message: &CANAerospaceMessage) -> bool {
+        let queued = self.queue_frame(to_frame(message));
+        self.pump();
+        queued
+    }
+
+    /// Queues a message of a bulk transfer, e.g. a log dump, which retries on its own. Returns
+    /// `false` without dropping anything once the queue is full except for the slots reserved
+    /// for the other messages.
+    pub fn transmit_bulk(&mut self, message: &CANAerospaceMessage) -> bool {
+        if self.tx_queue.len() + TX_RESERVED >= TX_QUEUE_LEN {
+            self.pump();
+            return false;
+        }
+        self.try_transmit(message)
+    }
+
+    /// Retries putting the message into a mailbox, bypassing the transmit queue, gives up after
+    /// `max_attempts` so that a silent bus cannot stall the caller. Used before the interrupts
+    /// are enabled.
+    pub fn transmit_blocking(&mut self, message: &CANAerospaceMessage, max_attempts: u32) -> bool {
+        let frame = to_frame(message);
+        (0..max_attempts).any(|_| self.load_mailbox(&frame))
+    }
+
+    /// Handles the transmit mailbox empty interrupt: refills the mailboxes from the queue.
+    pub fn on_transmit_interrupt(&mut self) {
+        self.tx.clear_interrupt_flags();
+        self.pump();
+    }
+
+    /// Reads the error counters of the controller and counts the transmission errors since the
+    /// last check. Returns the new state of the link, `true` when degraded, when it has changed.
+    ///
+    /// The controller recovers from bus-off by itself, see `initialize_canbus`.
+    pub fn check_errors(&mut self) -> Option<bool> {
+        self.error_status = ErrorStatus::from_esr(read_esr());
+        // An error between the read and the reset is missed, its rise of the counter is not
+        reset_last_error();
+        let tx_errors = self.tx_error_decoder.update(&self.error_status);
+        self.tx_errors = self.tx_errors.wrapping_add(tx_errors);
+        self.link.update(&self.error_status)
+    }
+
+    /// Error counters and flags as of the last [`CANDriver::check_errors`], along with the
+    /// counters of the driver.
+    pub fn health(&self) -> CanHealth {
+        CanHealth {
+            status: self.error_status,
+            rx_overruns: self.rx_overruns,
+            tx_errors: self.tx_errors,
+            tx_dropped: self.tx_dropped,
+            bus_off_count: self.link.bus_off_count(),
+            degraded: self.link.degraded(),
+        }
+    }
+
+    /// Empties receive FIFO 0, see [`RxFifo::receive`], the frames the firmware does not handle
+    /// are passed to CANaerospace-Lite.
+    pub fn receive(&mut self, on_message: impl FnMut(CANAerospaceMessage)) {
+        let link_rx = &mut self.link_rx;
+        self.fifo0.receive(on_message, |frame| {
+            // Frames are dropped if CANaerospace-Lite does not keep up
+            link_rx.enqueue(frame).ok();
+        });
+        let overruns = self.fifo0.take_overruns();
+        self.count_overruns(overruns);
+    }
+
+    /// Passes a frame of receive FIFO 1 the firmware does not handle to CANaerospace-Lite.
+    pub fn pass_to_link(&mut self, frame: Frame) {
+        self.link_rx.enqueue(frame).ok();
+    }
+
+    /// Frames waiting for CANaerospace-Lite.
+    pub fn link_pending(&self) -> usize {
+        self.link_rx.len()
+    }
+
+    /// Moves the frames queued by CANaerospace-Lite into the transmit queue, they stay in the link
+    /// queue as long as it is full.
+    pub fn flush_link(&mut self) {
+        while let Some(frame) = self.link_tx.peek() {
+            if !self.queue_frame(frame.clone()) {
+                break;
             }
+            self.link_tx.dequeue();
+        }
+        self.pump();
+    }
+
+    /// Counts frames lost by a receive FIFO, FIFO 1 is read outside of the driver.
+    pub fn count_overruns(&mut self, overruns: u32) {
+        self.rx_overruns = self.rx_overruns.wrapping_add(overruns);
+    }
+
+    /// Inserts the frame behind every queued frame of the same or a higher priority.
+    fn queue_frame(&mut self, frame: Frame) -> bool {
+        let key = arbitration_key(&frame);
+        let index = self
+            .tx_queue
+            .iter()
+            .position(|queued| arbitration_key(queued) > key)
+            .unwrap_or(self.tx_queue.len());
+        self.tx_queue.insert(index, frame).is_ok()
+    }
+
+    /// Moves queued frames into free mailboxes, highest priority first.
+    fn pump(&mut self) {
+        while !self.tx_queue.is_empty() {
+            let frame = self.tx_queue.remove(0);
+            if !self.load_mailbox(&frame) {
+                // Goes back to the front, nothing queued has a higher priority
+                self.tx_queue.insert(0, frame).ok();
+                break;
+            }
+        }
+    }
+
+    /// Puts the frame into a mailbox. When all mailboxes are busy bxcan may replace a pending frame
+    /// of lower priority, which is queued again.
+    fn load_mailbox(&mut self, frame: &Frame) -> bool {
+        match self.tx.transmit(frame) {
+            Ok(None) => true,
+            Ok(Some(replaced)) => {
+                if !self.queue_frame(replaced) {
+                    self.tx_dropped = self.tx_dropped.wrapping_add(1);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+// bxcan does not expose the error status register. Only its last error code is written, which
+// bxcan never uses.
+fn read_esr() -> u32 {
+    unsafe { (*CAN1::ptr()).esr.read().bits() }
+}
+
+/// Sets the last error code to `Software`, the controller overwrites it on the next error.
+fn reset_last_error() {
+    unsafe { (*CAN1::ptr()).esr.modify(|r, w| w.bits(r.bits() | ESR_LEC)) }
+}
+
+/// Lower keys win the bus arbitration.
+fn arbitration_key(frame: &Frame) -> u32 {
+    match frame.id() {
+        // The 11 bit base identifier is compared first
+        Id::Standard(id) => u32::from(id.as_raw()) << 18,
+        Id::Extended(id) => id.as_raw(),
+    }
+}
+
+/// Enables one list mode filter bank per four identifiers, for [`DATA_IDS`] into FIFO 0 then for
+/// [`PRIORITY_IDS`] into FIFO 1.
+pub fn enable_filters(filters: &mut MasterFilters<'_, Can<CAN1>>) {
+    let mut bank = 0;
+    for (ids, fifo) in [(DATA_IDS, Fifo::Fifo0), (PRIORITY_IDS, Fifo::Fifo1)] {
+        for ids in ids.chunks(IDS_PER_BANK) {
+            filters.enable_bank(bank, fifo, filter_bank(ids));
+            bank += 1;
         }
-        None
+    }
+}
+
+fn filter_bank(ids: &[u16]) -> [ListEntry16; IDS_PER_BANK] {
+    let entry = |id: u16| ListEntry16::data_frames_with_id(StandardId::new(id).unwrap());
+    // Unused entries repeat the first identifier
+    let mut bank = [entry(ids[0]); IDS_PER_BANK];
+    for (slot, id) in bank.iter_mut().zip(ids) {
+        *slot = entry(*id);
+    }
+    bank
+}
+
+fn to_frame(message: &CANAerospaceMessage) -> Frame {
+    Frame::from(&CANAerospaceFrame::from(*message))
+}
+
+fn node_message(frame: &Frame) -> Option<CANAerospaceMessage> {
+    // The node uses neither extended identifiers nor remote frames, and every CANaerospace message
+    // carries at least its 4 byte header
+    let id = match frame.id() {
+        Id::Standard(id) => id.as_raw(),
+        Id::Extended(_) => return None,
+    };
+    if frame.data()?.len() < 4 {
+        return None;
+    }
+    let message = CANAerospaceMessage::from(CANAerospaceFrame::from(frame.clone()));
+    match id {
+        SERVICE_REQUEST_ID if message.header.node_id == NODE_ID => Some(message),
+        BODY_LONG_ACC_ID | BARO_ALTITUDE_ID => Some(message),
+        _ => None,
+    }
+}
+
+impl CANAerospaceDriver for AeroLink {
+    fn send_frame(&mut self, frame: CANAerospaceFrame) {
+        // The frame is lost if the driver does not keep up
+        self.tx.enqueue(Frame::from(&frame)).ok();
+    }
+
+    fn recv_frame(&mut self) -> Option<CANAerospaceFrame> {
+        self.rx.dequeue().map(CANAerospaceFrame::from)
     }
 }
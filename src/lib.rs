@@ -1,10 +1,12 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 use state_governor::create_states;
+// Drives the CAN peripheral of the STM32F1, only built for the board
+#[cfg(target_os = "none")]
 pub mod can_driver;
 
 pub mod libs;
 pub use libs::*;
 
 // https://github.com/Badger-Embedded/Badger-Pike#engine-control
-create_states!(IDLE, READY, IGNITION, PROPULSION, BURNOUT);
+create_states!(IDLE, READY, IGNITION, PROPULSION, BURNOUT, ABORT);
@@ -0,0 +1,293 @@
+//! CANaerospace identifiers and codes of this node, and the messages it decodes and builds.
+//!
+//! The messages are the ones of CANaerospace-Lite. Flight data and commands are decoded in the
+//! receive interrupts and every message goes through the priority ordered transmit queue of the CAN
+//! driver, the frames the node does not handle itself are passed on to the library.
+
+use can_aerospace_lite::{
+    message::{CANAerospaceMessage, MessageHeader},
+    types::{DataType, MessageType},
+};
+
+/// CANaerospace node ID of the engine control board.
+pub const NODE_ID: u8 = 0xA;
+
+/// Emergency event channel of this node, state machine events are reported on it.
+pub const EVENT_ID: u16 = NODE_ID as u16;
+
+/// Message codes of the events reported on [`EVENT_ID`].
+pub mod event_code {
+    pub const BURNOUT: u8 = 1;
+    /// Data: the state whose dwell limit expired
+    pub const TIMEOUT: u8 = 2;
+    /// Data: 0 = command, 1 = charge failure, 2 = pyro fault, 3 = stuck transition
+    pub const ABORT: u8 = 3;
+    pub const LIFTOFF: u8 = 4;
+    /// Data: the target state whose pyro path was rejected
+    pub const TRANSITION_REJECTED: u8 = 5;
+    pub const LINK_DEGRADED: u8 = 6;
+    pub const LINK_RESTORED: u8 = 7;
+    /// Data: the number of events dropped since the previous report
+    pub const EVENTS_DROPPED: u8 = 8;
+    /// Data: the channel and its new continuity
+    pub const CONTINUITY_CHANGED: u8 = 9;
+}
+
+/// Flight data sent by the flight computer: body longitudinal acceleration [g] and barometric
+/// altitude [m].
+pub const BODY_LONG_ACC_ID: u16 = 300;
+pub const BARO_ALTITUDE_ID: u16 = 320;
+
+/// Telemetry of this node, user defined normal operation data.
+pub const NODE_STATUS_ID: u16 = 1800;
+pub const UPTIME_ID: u16 = 1801;
+pub const CONTINUITY_ID: u16 = 1802;
+pub const CAN_COUNTERS_ID: u16 = 1803;
+pub const CAN_ERRORS_ID: u16 = 1804;
+
+/// Low priority node service channel 0, requests to this node and their responses.
+pub const SERVICE_REQUEST_ID: u16 = 2000;
+pub const SERVICE_RESPONSE_ID: u16 = 2001;
+
+/// User defined service codes handled by this node.
+pub mod service_code {
+    /// Dumps the pyro action log
+    pub const PYRO_LOG: u8 = 100;
+    /// Dumps the state transition journal
+    pub const JOURNAL: u8 = 101;
+    /// Commands, see [`Command`]
+    pub const ARM: u8 = 110;
+    pub const IGNITE: u8 = 111;
+    pub const ABORT: u8 = 112;
+    pub const DISARM: u8 = 113;
+    pub const RESET_FAULT: u8 = 114;
+    pub const STATUS: u8 = 115;
+}
+
+/// Acknowledgement codes, the first data byte of a command response.
+pub mod command_ack {
+    pub const ACK: u8 = 0;
+    /// The command does not apply to the current state
+    pub const NOT_ALLOWED: u8 = 1;
+    /// The transition exists but its guard did not pass (e.g. no continuity)
+    pub const GUARD_FAILED: u8 = 2;
+    /// Another transition is in progress
+    pub const BUSY: u8 = 3;
+}
+
+/// Node status bytes of a command response sent without reading the status.
+pub const STATUS_UNKNOWN: u8 = 0xFF;
+
+/// Bits of the flags byte of the node status.
+pub mod status_flag {
+    pub const CONTINUITY_OK: u8 = 1 << 0;
+    pub const LOCKED_OUT: u8 = 1 << 1;
+    pub const ABORTED: u8 = 1 << 2;
+    /// The system is READY, in IGNITION or in PROPULSION
+    pub const ARMED: u8 = 1 << 3;
+    /// The firing capacitor is charged
+    pub const CHARGED: u8 = 1 << 4;
+    pub const LINK_DEGRADED: u8 = 1 << 5;
+}
+
+/// Bits of the fault byte of the node status, the latched abort reason.
+pub mod fault_flag {
+    pub const COMMAND: u8 = 1 << 0;
+    pub const CHARGE_FAILURE: u8 = 1 << 1;
+    pub const PYRO: u8 = 1 << 2;
+    pub const TRANSITION_STUCK: u8 = 1 << 3;
+}
+
+/// A message of this node on the CAN identifier, the service code is 0 for normal operation data.
+pub fn message(id: u16, message_code: u8, data: DataType) -> CANAerospaceMessage {
+    CANAerospaceMessage {
+        message_type: MessageType::from(id),
+        header: MessageHeader {
+            node_id: NODE_ID,
+            service_code: 0,
+            message_code,
+        },
+        data,
+    }
+}
+
+/// CAN identifier of the message.
+pub fn message_id(message: &CANAerospaceMessage) -> u16 {
+    u16::from(message.message_type)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlightData {
+    /// Body longitudinal acceleration [g]
+    Acceleration(f32),
+    /// Barometric altitude [m]
+    Altitude(f32),
+}
+
+impl FlightData {
+    /// Decodes the flight data carried by the message, other messages return `None`.
+    pub fn from_message(message: &CANAerospaceMessage) -> Option<Self> {
+        let value = match message.data {
+            DataType::FLOAT(value) => value,
+            _ => return None,
+        };
+        match message_id(message) {
+            BODY_LONG_ACC_ID => Some(FlightData::Acceleration(value)),
+            BARO_ALTITUDE_ID => Some(FlightData::Altitude(value)),
+            _ => None,
+        }
+    }
+}
+
+/// Node service requests this node responds to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServiceRequest {
+    PyroLog,
+    Journal,
+}
+
+impl ServiceRequest {
+    /// Decodes a request addressed to this node, other messages return `None`.
+    pub fn from_message(message: &CANAerospaceMessage) -> Option<Self> {
+        if message_id(message) != SERVICE_REQUEST_ID || message.header.node_id != NODE_ID {
+            return None;
+        }
+        match message.header.service_code {
+            service_code::PYRO_LOG => Some(ServiceRequest::PyroLog),
+            service_code::JOURNAL => Some(ServiceRequest::Journal),
+            _ => None,
+        }
+    }
+}
+
+/// Commands sent by the ground station or the flight computer as node service requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Goes READY: charges the firing capacitor
+    Arm,
+    Ignite,
+    Abort,
+    /// Goes back from READY to IDLE and discharges the capacitor
+    Disarm,
+    /// Leaves ABORT
+    ResetFault,
+    /// Only responds with the node status
+    Status,
+}
+
+impl Command {
+    /// Decodes a command addressed to this node along with its message code, which the response
+    /// echoes. Other messages return `None`.
+    pub fn from_message(message: &CANAerospaceMessage) -> Option<(Self, u8)> {
+        if message_id(message) != SERVICE_REQUEST_ID || message.header.node_id != NODE_ID {
+            return None;
+        }
+        let command = match message.header.service_code {
+            service_code::ARM => Command::Arm,
+            service_code::IGNITE => Command::Ignite,
+            service_code::ABORT => Command::Abort,
+            service_code::DISARM => Command::Disarm,
+            service_code::RESET_FAULT => Command::ResetFault,
+            service_code::STATUS => Command::Status,
+            _ => return None,
+        };
+        Some((command, message.header.message_code))
+    }
+
+    pub fn service_code(&self) -> u8 {
+        match self {
+            Command::Arm => service_code::ARM,
+            Command::Ignite => service_code::IGNITE,
+            Command::Abort => service_code::ABORT,
+            Command::Disarm => service_code::DISARM,
+            Command::ResetFault => service_code::RESET_FAULT,
+            Command::Status => service_code::STATUS,
+        }
+    }
+
+    /// Response to the command: the acknowledgement code, see [`command_ack`], followed by three
+    /// bytes of node status.
+    pub fn response(&self, message_code: u8, ack: u8, status: [u8; 3]) -> CANAerospaceMessage {
+        let mut response = message(
+            SERVICE_RESPONSE_ID,
+            message_code,
+            DataType::UCHAR4([ack, status[0], status[1], status[2]]),
+        );
+        response.header.service_code = self.service_code();
+        response
+    }
+
+    /// Response to a command that could not even be handed to the command handler, the node status
+    /// bytes are [`STATUS_UNKNOWN`].
+    pub fn refusal(&self, message_code: u8) -> CANAerospaceMessage {
+        self.response(message_code, command_ack::BUSY, [STATUS_UNKNOWN; 3])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flight_data() {
+        let data = message(BODY_LONG_ACC_ID, 0, DataType::FLOAT(1.5));
+        assert_eq!(
+            FlightData::from_message(&data),
+            Some(FlightData::Acceleration(1.5))
+        );
+        let data = message(BARO_ALTITUDE_ID, 0, DataType::FLOAT(12.0));
+        assert_eq!(
+            FlightData::from_message(&data),
+            Some(FlightData::Altitude(12.0))
+        );
+
+        let data = message(BODY_LONG_ACC_ID, 0, DataType::ULONG(0));
+        assert_eq!(FlightData::from_message(&data), None);
+    }
+
+    #[test]
+    fn service_request() {
+        let mut request = message(SERVICE_REQUEST_ID, 0, DataType::NODATA);
+        request.header.service_code = service_code::PYRO_LOG;
+        assert_eq!(
+            ServiceRequest::from_message(&request),
+            Some(ServiceRequest::PyroLog)
+        );
+
+        request.header.node_id = NODE_ID + 1;
+        assert_eq!(ServiceRequest::from_message(&request), None);
+    }
+
+    #[test]
+    fn command() {
+        let mut request = message(SERVICE_REQUEST_ID, 7, DataType::NODATA);
+        request.header.service_code = service_code::DISARM;
+        assert_eq!(Command::from_message(&request), Some((Command::Disarm, 7)));
+        assert_eq!(ServiceRequest::from_message(&request), None);
+
+        let response = Command::Disarm.response(7, command_ack::BUSY, [1, 2, 3]);
+        assert_eq!(message_id(&response), SERVICE_RESPONSE_ID);
+        assert_eq!(response.header.service_code, service_code::DISARM);
+        assert_eq!(response.header.message_code, 7);
+        assert_eq!(
+            response.data,
+            DataType::UCHAR4([command_ack::BUSY, 1, 2, 3])
+        );
+
+        let refusal = Command::Abort.refusal(8);
+        assert_eq!(refusal.header.service_code, service_code::ABORT);
+        assert_eq!(refusal.header.message_code, 8);
+        assert_eq!(
+            refusal.data,
+            DataType::UCHAR4([
+                command_ack::BUSY,
+                STATUS_UNKNOWN,
+                STATUS_UNKNOWN,
+                STATUS_UNKNOWN
+            ])
+        );
+
+        request.header.service_code = 120;
+        assert_eq!(Command::from_message(&request), None);
+    }
+}
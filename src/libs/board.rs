@@ -0,0 +1,116 @@
+//! Pyro channels and channel groups of the board revision selected with a cargo feature, shared by
+//! the firmware and the host simulator. The pins of a revision are described by the firmware's
+//! `board` module.
+
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::{
+    pin::Output,
+    pyro::{
+        FireMode, PyroChannel, PyroChannelName, PyroController, PyroError, MAX_GROUPS,
+        MAX_GROUP_CHANNELS,
+    },
+};
+
+#[cfg(feature = "pike-rev1")]
+pub use pike_rev1::*;
+
+#[cfg(not(feature = "pike-rev1"))]
+compile_error!("No board revision selected, enable one of the board features (e.g. `pike-rev1`)");
+
+#[cfg(feature = "pike-rev1")]
+mod pike_rev1 {
+    use crate::pyro::{FireMode, PyroChannelName};
+
+    pub const PYRO_CHANNELS: usize = 3;
+
+    /// Pyro channels with continuity sensing, in the order they are added to the controller.
+    pub const PYRO_CHANNEL_NAMES: [PyroChannelName; PYRO_CHANNELS] = [
+        PyroChannelName::Pyro1,
+        PyroChannelName::Ignition,
+        PyroChannelName::Pyro2,
+    ];
+
+    /// Channel groups: pyro actions requested on any channel must never end up at the ignition
+    /// e-match.
+    pub const PYRO_GROUPS: &[(PyroChannelName, FireMode, &[PyroChannelName])] = &[(
+        PyroChannelName::Any,
+        FireMode::AnyWithContinuity,
+        &[PyroChannelName::Pyro1, PyroChannelName::Pyro2],
+    )];
+}
+
+const _: () = assert!(
+    groups_are_valid(PYRO_GROUPS, &PYRO_CHANNEL_NAMES),
+    "PYRO_GROUPS does not fit the pyro controller or names a channel the board does not have"
+);
+
+/// Builds the pyro controller of the board from its pins and installs the channel groups.
+///
+/// Cannot fail for the channels of the board, the group table is checked at compile time.
+pub fn pyro_controller<IO: OutputPin>(
+    charge: Output<IO, false>,
+    discharge: Output<IO, true>,
+    channels: [(PyroChannelName, Output<IO, false>); PYRO_CHANNELS],
+) -> Result<PyroController<PYRO_CHANNELS, IO>, PyroError> {
+    let mut pyro_controller = PyroController::new(charge, discharge);
+    for (name, pin) in channels {
+        pyro_controller.add_channel(PyroChannel::new(name, pin))?;
+    }
+    for (name, mode, channels) in PYRO_GROUPS {
+        pyro_controller.add_group(*name, *mode, channels)?;
+    }
+    Ok(pyro_controller)
+}
+
+/// Whether [`PyroController::add_group`] accepts every group of the table once `channels` have
+/// been added.
+pub const fn groups_are_valid(
+    groups: &[(PyroChannelName, FireMode, &[PyroChannelName])],
+    channels: &[PyroChannelName],
+) -> bool {
+    if groups.len() > MAX_GROUPS {
+        return false;
+    }
+    let mut group = 0;
+    while group < groups.len() {
+        let members = groups[group].2;
+        if members.is_empty() || members.len() > MAX_GROUP_CHANNELS {
+            return false;
+        }
+        let mut member = 0;
+        while member < members.len() {
+            let mut channel = 0;
+            while channel < channels.len() && channels[channel] as u8 != members[member] as u8 {
+                channel += 1;
+            }
+            if channel == channels.len() {
+                return false;
+            }
+            member += 1;
+        }
+        group += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: [PyroChannelName; 2] = [PyroChannelName::Pyro1, PyroChannelName::Pyro2];
+
+    #[test]
+    fn checks_group_table() {
+        let pyros: &[PyroChannelName] = &[PyroChannelName::Pyro1, PyroChannelName::Pyro2];
+        let any = (PyroChannelName::Any, FireMode::AnyWithContinuity, pyros);
+        assert!(groups_are_valid(&[any], &CHANNELS));
+        assert!(!groups_are_valid(&[any; MAX_GROUPS + 1], &CHANNELS));
+
+        let ignition: &[PyroChannelName] = &[PyroChannelName::Ignition];
+        let backup = (PyroChannelName::Ignition, FireMode::Simultaneous, ignition);
+        assert!(!groups_are_valid(&[any, backup], &CHANNELS));
+        let empty = (PyroChannelName::Any, FireMode::Simultaneous, &[][..]);
+        assert!(!groups_are_valid(&[empty], &CHANNELS));
+    }
+}
@@ -0,0 +1,97 @@
+/// Parameters of the motor burnout detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurnoutConfig {
+    /// Longitudinal acceleration [g] the motor is considered burnt out below
+    pub threshold: f32,
+    /// Time the acceleration has to stay below the threshold
+    pub hold_ms: u32,
+    /// Burn time before a drop is taken into account, ignores the transients at ignition
+    pub min_burn_ms: u32,
+}
+
+/// Detects the motor burnout from the longitudinal acceleration during propulsion.
+pub struct BurnoutDetector {
+    config: BurnoutConfig,
+    burn_started_at: Option<u32>,
+    below_since: Option<u32>,
+    detected: bool,
+}
+
+impl BurnoutDetector {
+    pub fn new(config: BurnoutConfig) -> Self {
+        Self {
+            config,
+            burn_started_at: None,
+            below_since: None,
+            detected: false,
+        }
+    }
+
+    pub fn config(&self) -> BurnoutConfig {
+        self.config
+    }
+
+    /// Evaluates an acceleration sample, the burn starts with the first sample.
+    ///
+    /// Returns `true` once, when the burnout is detected.
+    pub fn update(&mut self, now_ms: u32, acceleration: f32) -> bool {
+        if self.detected {
+            return false;
+        }
+        let burn_started_at = *self.burn_started_at.get_or_insert(now_ms);
+
+        if acceleration >= self.config.threshold
+            || now_ms.wrapping_sub(burn_started_at) < self.config.min_burn_ms
+        {
+            self.below_since = None;
+            return false;
+        }
+        let below_since = *self.below_since.get_or_insert(now_ms);
+        self.detected = now_ms.wrapping_sub(below_since) >= self.config.hold_ms;
+        self.detected
+    }
+
+    /// Prepares the detector for the next burn.
+    pub fn reset(&mut self) {
+        self.burn_started_at = None;
+        self.below_since = None;
+        self.detected = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: BurnoutConfig = BurnoutConfig {
+        threshold: 0.5,
+        hold_ms: 200,
+        min_burn_ms: 1000,
+    };
+
+    #[test]
+    fn detects_sustained_drop_once() {
+        let mut detector = BurnoutDetector::new(CONFIG);
+        assert!(!detector.update(0, 8.0));
+        assert!(!detector.update(1100, 0.2));
+        // A single sample above the threshold restarts the hold time
+        assert!(!detector.update(1200, 0.6));
+        assert!(!detector.update(1300, 0.1));
+        assert!(!detector.update(1499, 0.1));
+        assert!(detector.update(1500, 0.1));
+        assert!(!detector.update(1600, 0.1));
+    }
+
+    #[test]
+    fn ignores_drop_before_min_burn_time() {
+        let mut detector = BurnoutDetector::new(CONFIG);
+        assert!(!detector.update(0, 0.1));
+        assert!(!detector.update(900, 0.1));
+        assert!(!detector.update(1000, 0.1));
+        assert!(detector.update(1200, 0.1));
+
+        detector.reset();
+        assert!(!detector.update(5000, 0.1));
+        assert!(!detector.update(5300, 0.1));
+    }
+}
@@ -0,0 +1,265 @@
+/// Error of the last frame the CAN controller sent or received, `LEC` field of the error status
+/// register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LastErrorCode {
+    None,
+    Stuff,
+    Form,
+    Acknowledgement,
+    BitRecessive,
+    BitDominant,
+    Crc,
+    /// Set by software, never by the controller: no error since the driver last checked
+    Software,
+}
+
+impl LastErrorCode {
+    /// Value of the `LEC` field.
+    pub fn code(&self) -> u8 {
+        match self {
+            LastErrorCode::None => 0,
+            LastErrorCode::Stuff => 1,
+            LastErrorCode::Form => 2,
+            LastErrorCode::Acknowledgement => 3,
+            LastErrorCode::BitRecessive => 4,
+            LastErrorCode::BitDominant => 5,
+            LastErrorCode::Crc => 6,
+            LastErrorCode::Software => 7,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b111 {
+            0 => LastErrorCode::None,
+            1 => LastErrorCode::Stuff,
+            2 => LastErrorCode::Form,
+            3 => LastErrorCode::Acknowledgement,
+            4 => LastErrorCode::BitRecessive,
+            5 => LastErrorCode::BitDominant,
+            6 => LastErrorCode::Crc,
+            _ => LastErrorCode::Software,
+        }
+    }
+}
+
+/// Error counters and flags of the CAN controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ErrorStatus {
+    pub tx_error_count: u8,
+    pub rx_error_count: u8,
+    pub last_error: LastErrorCode,
+    /// One of the error counters has reached 96
+    pub warning: bool,
+    /// One of the error counters has exceeded 127, the controller only sends passive error flags
+    pub passive: bool,
+    /// The transmit error counter has exceeded 255, the controller has left the bus
+    pub bus_off: bool,
+}
+
+impl ErrorStatus {
+    /// Decodes the error status register (`CAN_ESR`).
+    pub fn from_esr(esr: u32) -> Self {
+        Self {
+            tx_error_count: (esr >> 16) as u8,
+            rx_error_count: (esr >> 24) as u8,
+            last_error: LastErrorCode::from_bits(esr >> 4),
+            warning: esr & (1 << 0) != 0,
+            passive: esr & (1 << 1) != 0,
+            bus_off: esr & (1 << 2) != 0,
+        }
+    }
+}
+
+impl Default for ErrorStatus {
+    fn default() -> Self {
+        Self::from_esr(0)
+    }
+}
+
+/// Snapshot of the CAN link health.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct CanHealth {
+    pub status: ErrorStatus,
+    /// Frames lost because a receive FIFO was full
+    pub rx_overruns: u32,
+    /// Transmissions that failed with an error, they are retried by the controller. Counted by a
+    /// [`TxErrorDecoder`]
+    pub tx_errors: u32,
+    /// Messages dropped because the transmit queue was full
+    pub tx_dropped: u32,
+    /// Times the controller has gone bus-off
+    pub bus_off_count: u32,
+    pub degraded: bool,
+}
+
+impl CanHealth {
+    /// The counters of the driver, published as `[tx errors, rx overruns, tx dropped, bus-off
+    /// count]`, each saturating at 255.
+    pub fn counter_data(&self) -> [u8; 4] {
+        let saturate = |count: u32| count.min(u32::from(u8::MAX)) as u8;
+        [
+            saturate(self.tx_errors),
+            saturate(self.rx_overruns),
+            saturate(self.tx_dropped),
+            saturate(self.bus_off_count),
+        ]
+    }
+
+    /// The error status of the controller, published as `[transmit error counter, receive error
+    /// counter, last error code, 0]`.
+    pub fn error_data(&self) -> [u8; 4] {
+        [
+            self.status.tx_error_count,
+            self.status.rx_error_count,
+            self.status.last_error.code(),
+            0,
+        ]
+    }
+}
+
+/// Counts the transmission errors between two samples of the error status.
+///
+/// Every transmit error adds 8 to the transmit error counter and every successful transmission
+/// takes 1 off it, so a rise of the counter means at least `rise / 8` errors, rounded up.
+/// Acknowledgement and bit errors are only seen by the transmitter, such a last error code counts
+/// one error even when successful transmissions have made up for the rise. The code has to be reset
+/// to [`LastErrorCode::Software`] after every sample.
+#[derive(Default)]
+pub struct TxErrorDecoder {
+    tx_error_count: u8,
+}
+
+impl TxErrorDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of transmission errors since the previous sample.
+    pub fn update(&mut self, status: &ErrorStatus) -> u32 {
+        let rise = status.tx_error_count.saturating_sub(self.tx_error_count);
+        self.tx_error_count = status.tx_error_count;
+        let counted = (u32::from(rise) + 7) / 8;
+        let transmitter_error = matches!(
+            status.last_error,
+            LastErrorCode::Acknowledgement
+                | LastErrorCode::BitRecessive
+                | LastErrorCode::BitDominant
+        );
+        counted.max(u32::from(transmitter_error))
+    }
+}
+
+/// Decides whether the link is degraded from the error status, with hysteresis: the link
+/// degrades when the controller goes error passive or bus-off, and is restored once both error
+/// counters are back below the warning limit.
+#[derive(Default)]
+pub struct LinkMonitor {
+    degraded: bool,
+    bus_off: bool,
+    bus_off_count: u32,
+}
+
+impl LinkMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the new state of the link, `true` when degraded, when it has changed.
+    pub fn update(&mut self, status: &ErrorStatus) -> Option<bool> {
+        if status.bus_off && !self.bus_off {
+            self.bus_off_count = self.bus_off_count.wrapping_add(1);
+        }
+        self.bus_off = status.bus_off;
+
+        let degraded = if self.degraded {
+            status.bus_off || status.warning
+        } else {
+            status.bus_off || status.passive
+        };
+        if degraded == self.degraded {
+            return None;
+        }
+        self.degraded = degraded;
+        Some(degraded)
+    }
+
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn bus_off_count(&self) -> u32 {
+        self.bus_off_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_error_status() {
+        let status = ErrorStatus::from_esr(0x05a0_0033);
+        assert_eq!(status.tx_error_count, 0xa0);
+        assert_eq!(status.rx_error_count, 0x05);
+        assert_eq!(status.last_error, LastErrorCode::Acknowledgement);
+        assert!(status.warning);
+        assert!(status.passive);
+        assert!(!status.bus_off);
+    }
+
+    #[test]
+    fn decodes_tx_errors() {
+        let mut decoder = TxErrorDecoder::new();
+        let sample = |tx_error_count: u8, last_error: LastErrorCode| ErrorStatus {
+            tx_error_count,
+            last_error,
+            ..ErrorStatus::default()
+        };
+
+        assert_eq!(decoder.update(&sample(0, LastErrorCode::Software)), 0);
+        // Two errors, partly made up for by successful transmissions
+        assert_eq!(decoder.update(&sample(14, LastErrorCode::BitDominant)), 2);
+        // The counter falls with successful transmissions, a receive error is not counted
+        assert_eq!(decoder.update(&sample(10, LastErrorCode::Software)), 0);
+        assert_eq!(decoder.update(&sample(10, LastErrorCode::Crc)), 0);
+        // One error, made up for completely before the sample
+        assert_eq!(
+            decoder.update(&sample(2, LastErrorCode::Acknowledgement)),
+            1
+        );
+        // Recovery from bus-off resets the counter
+        assert_eq!(
+            decoder.update(&sample(248, LastErrorCode::Acknowledgement)),
+            31
+        );
+        assert_eq!(decoder.update(&sample(0, LastErrorCode::Software)), 0);
+    }
+
+    #[test]
+    fn link_degrades_with_hysteresis() {
+        let mut monitor = LinkMonitor::new();
+        let warning = ErrorStatus {
+            warning: true,
+            ..ErrorStatus::default()
+        };
+        let passive = ErrorStatus {
+            passive: true,
+            ..warning
+        };
+        let bus_off = ErrorStatus {
+            bus_off: true,
+            ..passive
+        };
+
+        assert_eq!(monitor.update(&warning), None);
+        assert_eq!(monitor.update(&passive), Some(true));
+        assert_eq!(monitor.update(&bus_off), None);
+        assert_eq!(monitor.bus_off_count(), 1);
+        // Recovered from bus-off, but the counters are still high
+        assert_eq!(monitor.update(&warning), None);
+        assert_eq!(monitor.update(&ErrorStatus::default()), Some(false));
+
+        assert_eq!(monitor.update(&bus_off), Some(true));
+        assert_eq!(monitor.bus_off_count(), 2);
+    }
+}
@@ -0,0 +1,121 @@
+/// Limits for charging the firing capacitor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChargeConfig {
+    /// ADC reading of the charge voltage that has to be reached
+    pub threshold: u16,
+    /// Maximum time the capacitor may take to reach the threshold
+    pub timeout_ms: u32,
+    /// Time after which the capacitor is taken as charged on a board without charge sense
+    pub charge_time_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChargeStatus {
+    Charging,
+    Charged,
+    Failed,
+}
+
+/// Tracks a single charge cycle of the firing capacitor.
+pub struct ChargeMonitor {
+    config: ChargeConfig,
+    started_at: Option<u32>,
+    status: ChargeStatus,
+}
+
+impl ChargeMonitor {
+    pub fn new(config: ChargeConfig) -> Self {
+        Self {
+            config,
+            started_at: None,
+            status: ChargeStatus::Charging,
+        }
+    }
+
+    pub fn config(&self) -> ChargeConfig {
+        self.config
+    }
+
+    /// Evaluates a charge voltage reading, the cycle starts with the first reading.
+    ///
+    /// Returns the outcome of the cycle once, when it turns into `Charged` or `Failed`.
+    pub fn update(&mut self, now_ms: u32, reading: u16) -> Option<ChargeStatus> {
+        if self.status != ChargeStatus::Charging {
+            return None;
+        }
+        let started_at = *self.started_at.get_or_insert(now_ms);
+
+        if reading >= self.config.threshold {
+            self.status = ChargeStatus::Charged;
+        } else if now_ms.wrapping_sub(started_at) >= self.config.timeout_ms {
+            self.status = ChargeStatus::Failed;
+        } else {
+            return None;
+        }
+        Some(self.status)
+    }
+
+    /// Times the charge cycle on a board without charge sense, the cycle starts with the first
+    /// call. A timed charge cannot fail.
+    ///
+    /// Returns `Charged` once, when the charge time has elapsed.
+    pub fn update_timed(&mut self, now_ms: u32) -> Option<ChargeStatus> {
+        if self.status != ChargeStatus::Charging {
+            return None;
+        }
+        let started_at = *self.started_at.get_or_insert(now_ms);
+
+        if now_ms.wrapping_sub(started_at) >= self.config.charge_time_ms {
+            self.status = ChargeStatus::Charged;
+            Some(self.status)
+        } else {
+            None
+        }
+    }
+
+    /// Prepares the monitor for the next charge cycle.
+    pub fn reset(&mut self) {
+        self.started_at = None;
+        self.status = ChargeStatus::Charging;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: ChargeConfig = ChargeConfig {
+        threshold: 3000,
+        timeout_ms: 1000,
+        charge_time_ms: 500,
+    };
+
+    #[test]
+    fn reports_charged_once() {
+        let mut monitor = ChargeMonitor::new(CONFIG);
+        assert_eq!(monitor.update(100, 1000), None);
+        assert_eq!(monitor.update(600, 3000), Some(ChargeStatus::Charged));
+        assert_eq!(monitor.update(700, 3100), None);
+    }
+
+    #[test]
+    fn fails_after_timeout() {
+        let mut monitor = ChargeMonitor::new(CONFIG);
+        assert_eq!(monitor.update(100, 1000), None);
+        assert_eq!(monitor.update(1099, 2999), None);
+        assert_eq!(monitor.update(1100, 2999), Some(ChargeStatus::Failed));
+
+        monitor.reset();
+        assert_eq!(monitor.update(5000, 1000), None);
+        assert_eq!(monitor.update(5100, 3500), Some(ChargeStatus::Charged));
+    }
+
+    #[test]
+    fn timed_charge() {
+        let mut monitor = ChargeMonitor::new(CONFIG);
+        assert_eq!(monitor.update_timed(100), None);
+        assert_eq!(monitor.update_timed(599), None);
+        assert_eq!(monitor.update_timed(600), Some(ChargeStatus::Charged));
+        assert_eq!(monitor.update_timed(700), None);
+    }
+}
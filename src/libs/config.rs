@@ -0,0 +1,55 @@
+//! Tuning of the engine control, shared by the firmware and the host simulator.
+
+use crate::{
+    burnout::BurnoutConfig, charge::ChargeConfig, engine::DwellLimit, liftoff::LiftoffConfig,
+    StateEnum,
+};
+
+// Firing capacitor has to reach the threshold (ADC reading of the charge voltage) within the timeout.
+// A board without charge sense waits for the charge time, which has to cover the slowest charge of
+// the capacitor.
+pub const CHARGE: ChargeConfig = ChargeConfig {
+    threshold: 3000,
+    timeout_ms: 5000,
+    charge_time_ms: 3000,
+};
+
+// Burnout: longitudinal acceleration [g] below the threshold for the hold time, after the minimum
+// burn time
+pub const BURNOUT: BurnoutConfig = BurnoutConfig {
+    threshold: 0.5,
+    hold_ms: 200,
+    min_burn_ms: 1000,
+};
+
+// Lift-off: longitudinal acceleration [g] above the threshold for the hold time, or an altitude
+// gain [m] over the pad altitude
+pub const LIFTOFF: LiftoffConfig = LiftoffConfig {
+    acceleration: 2.0,
+    hold_ms: 100,
+    altitude_gain: 10.0,
+};
+
+// After a failed ignition the motor may still hang-fire, arming is refused for this time
+pub const HANGFIRE_LOCKOUT_MS: u32 = 60_000;
+
+// Longest time the system may stay in a state before falling back, see the engine transition table
+pub const DWELL_LIMITS: &[DwellLimit] = &[
+    // The firing capacitor must not stay charged
+    DwellLimit {
+        state: StateEnum::READY,
+        max_ms: 60_000,
+    },
+    // Ignition window, lift-off has to be seen within this time or the ignition has failed
+    DwellLimit {
+        state: StateEnum::IGNITION,
+        max_ms: 5_000,
+    },
+];
+
+// Longest time a step of a pyro transition may take, longer than the charge timeout so that a
+// charge failure is reported as such
+pub const TRANSITION_STEP_TIMEOUT_MS: u32 = 10_000;
+
+// Continuity and charge are sampled at this period
+pub const MONITOR_PERIOD_MS: u32 = 100;
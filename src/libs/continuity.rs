@@ -0,0 +1,59 @@
+/// Continuity of a pyro channel, measured over the sense input of the channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Continuity {
+    /// Not measured yet
+    Unknown,
+    /// No e-match connected or the bridge wire is broken
+    Open,
+    Ok,
+    /// The channel output is shorted to ground
+    Short,
+}
+
+/// ADC limits used to classify a sense reading.
+///
+/// The sense input is pulled up, so an open channel reads close to full scale and a shorted one
+/// reads close to zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContinuityThresholds {
+    pub short_below: u16,
+    pub open_above: u16,
+}
+
+impl ContinuityThresholds {
+    pub const fn new(short_below: u16, open_above: u16) -> Self {
+        Self {
+            short_below,
+            open_above,
+        }
+    }
+}
+
+impl Default for ContinuityThresholds {
+    fn default() -> Self {
+        // 12-bit ADC
+        Self::new(200, 3500)
+    }
+}
+
+impl Continuity {
+    /// Code of the continuity in the telemetry.
+    pub fn code(&self) -> u8 {
+        match self {
+            Continuity::Unknown => 0,
+            Continuity::Open => 1,
+            Continuity::Ok => 2,
+            Continuity::Short => 3,
+        }
+    }
+
+    pub fn from_reading(reading: u16, thresholds: &ContinuityThresholds) -> Self {
+        if reading < thresholds.short_below {
+            Continuity::Short
+        } else if reading > thresholds.open_above {
+            Continuity::Open
+        } else {
+            Continuity::Ok
+        }
+    }
+}
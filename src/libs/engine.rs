@@ -0,0 +1,691 @@
+//! Transition table of the engine control state machine, interpreted by the state handler.
+
+use crate::{
+    aero::{status_flag, Command},
+    continuity::Continuity,
+    event::{Fault, StateEvent},
+    pyro::{PyroChannelName, PyroState},
+    state::{Step, StepGuard},
+    StateEnum,
+};
+
+/// What causes a system state transition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// The state has been requested (e.g. by the flight computer)
+    Request(StateEnum),
+    Event(StateEvent),
+    /// Any fault event, only used in the transition table
+    Fault,
+    /// A continuity change of any channel to something else than OK, only used in the transition
+    /// table
+    ContinuityLost,
+}
+
+impl Trigger {
+    /// Whether `trigger` fires this trigger of the transition table.
+    pub fn matches(&self, trigger: &Trigger) -> bool {
+        match (self, trigger) {
+            (Trigger::Fault, Trigger::Event(StateEvent::Fault(_))) => true,
+            (
+                Trigger::ContinuityLost,
+                Trigger::Event(StateEvent::ContinuityChanged(_, continuity)),
+            ) => *continuity != Continuity::Ok,
+            _ => self == trigger,
+        }
+    }
+}
+
+/// Why the system has entered ABORT, the first reason is latched until the reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbortReason {
+    Command,
+    Fault(Fault),
+}
+
+impl AbortReason {
+    pub fn from_trigger(trigger: &Trigger) -> Self {
+        match trigger {
+            Trigger::Event(StateEvent::Fault(fault)) => AbortReason::Fault(*fault),
+            _ => AbortReason::Command,
+        }
+    }
+}
+
+/// Inputs the transition guards are evaluated against.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Conditions {
+    /// Every pyro channel has continuity
+    pub continuity_ok: bool,
+    /// Latched when ABORT is entered, cleared by the reset
+    pub abort_reason: Option<AbortReason>,
+    /// Re-arming is refused after a failed ignition until the hang-fire lockout has passed
+    pub locked_out: bool,
+    /// The CAN link is error passive or bus-off, arming is refused until it is restored
+    pub link_degraded: bool,
+}
+
+impl Conditions {
+    /// Flags byte of the node status, see [`status_flag`].
+    pub fn status_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.continuity_ok {
+            flags |= status_flag::CONTINUITY_OK;
+        }
+        if self.locked_out {
+            flags |= status_flag::LOCKED_OUT;
+        }
+        if self.abort_reason.is_some() {
+            flags |= status_flag::ABORTED;
+        }
+        if self.link_degraded {
+            flags |= status_flag::LINK_DEGRADED;
+        }
+        flags
+    }
+}
+
+pub type Guard = fn(&Conditions) -> bool;
+
+pub type PyroStep = Step<PyroState, Conditions>;
+
+/// Time the capacitor is held in CHARGING after lift-off.
+pub const PROPULSION_CHARGE_HOLD_MS: u32 = 3000;
+
+pub struct Transition {
+    pub source: StateEnum,
+    pub trigger: Trigger,
+    /// Has to pass for the transition to be taken, `None` always passes
+    pub guard: Option<Guard>,
+    /// Pyro states the controller walks through before the target state is committed
+    pub pyro_path: &'static [PyroStep],
+    /// The target state is committed as soon as the transition is taken and the pyro path runs
+    /// afterwards, as an action of the target state. A transition from the target state cancels it.
+    pub commit_first: bool,
+    pub target: StateEnum,
+    /// Starts the hang-fire lockout once the transition is taken
+    pub lockout: bool,
+}
+
+/// Whether the vehicle is flying in `state`. Faults do not abort in flight: ABORT safes every pyro
+/// output, the recovery ones included, so only a commanded abort leaves a flight state. The fault
+/// is reported and the pyro actions of the state are given up.
+pub fn in_flight(state: StateEnum) -> bool {
+    matches!(state, StateEnum::PROPULSION | StateEnum::BURNOUT)
+}
+
+/// Maximum time the system may stay in a state, [`StateEvent::Timeout`] is raised when it expires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DwellLimit {
+    pub state: StateEnum,
+    pub max_ms: u32,
+}
+
+/// Returns the dwell limit of `state`, `None` if the state may be held indefinitely.
+pub fn max_dwell_ms(limits: &[DwellLimit], state: StateEnum) -> Option<u32> {
+    limits
+        .iter()
+        .find(|limit| limit.state == state)
+        .map(|limit| limit.max_ms)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransitionError {
+    /// The trigger does not lead anywhere from the current state
+    NotAllowed,
+    /// A transition exists but its guard did not pass
+    GuardFailed,
+}
+
+fn continuity_ok(conditions: &Conditions) -> bool {
+    conditions.continuity_ok
+}
+
+fn link_ok(conditions: &Conditions) -> bool {
+    !conditions.link_degraded
+}
+
+fn can_arm(conditions: &Conditions) -> bool {
+    conditions.continuity_ok && !conditions.locked_out && !conditions.link_degraded
+}
+
+/// The pyro controller is safed directly on ABORT, without walking a pyro path that could be
+/// refused.
+const fn abort(source: StateEnum, trigger: Trigger) -> Transition {
+    Transition {
+        source,
+        trigger,
+        guard: None,
+        pyro_path: &[],
+        commit_first: false,
+        target: StateEnum::ABORT,
+        lockout: false,
+    }
+}
+
+// https://github.com/Badger-Embedded/Badger-Pike#engine-control
+pub const TRANSITIONS: &[Transition] = &[
+    Transition {
+        source: StateEnum::IDLE,
+        trigger: Trigger::Request(StateEnum::READY),
+        guard: Some(can_arm),
+        // Continuity and the link are checked again once the capacitor is charged, the link may
+        // degrade while it charges
+        pyro_path: &[
+            PyroStep::new(PyroState::CHARGING),
+            PyroStep::guarded(
+                PyroState::READY,
+                StepGuard::All(&[
+                    StepGuard::Event(StateEvent::ChargeComplete),
+                    StepGuard::Check(continuity_ok),
+                    StepGuard::Check(link_ok),
+                ]),
+            ),
+        ],
+        commit_first: false,
+        target: StateEnum::READY,
+        lockout: false,
+    },
+    // Disarm
+    Transition {
+        source: StateEnum::READY,
+        trigger: Trigger::Request(StateEnum::IDLE),
+        guard: None,
+        pyro_path: &[
+            PyroStep::new(PyroState::IDLE),
+            PyroStep::new(PyroState::DISCHARGING),
+        ],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: false,
+    },
+    Transition {
+        source: StateEnum::READY,
+        trigger: Trigger::Request(StateEnum::IGNITION),
+        guard: Some(continuity_ok),
+        pyro_path: &[PyroStep::new(PyroState::FIRING(PyroChannelName::Ignition))],
+        commit_first: false,
+        target: StateEnum::IGNITION,
+        lockout: false,
+    },
+    // Ignition is successful, the capacitor is charged again for the recovery pyros and held in
+    // CHARGING for a fixed time. PROPULSION is entered right away, burnout is detected meanwhile.
+    Transition {
+        source: StateEnum::IGNITION,
+        trigger: Trigger::Event(StateEvent::Liftoff),
+        guard: None,
+        pyro_path: &[
+            PyroStep::new(PyroState::CHARGING),
+            PyroStep::guarded(
+                PyroState::READY,
+                StepGuard::All(&[
+                    StepGuard::Event(StateEvent::ChargeComplete),
+                    StepGuard::Elapsed(PROPULSION_CHARGE_HOLD_MS),
+                ]),
+            ),
+        ],
+        commit_first: true,
+        target: StateEnum::PROPULSION,
+        lockout: false,
+    },
+    Transition {
+        source: StateEnum::PROPULSION,
+        trigger: Trigger::Event(StateEvent::Burnout),
+        guard: None,
+        // Safes the pyro controller. READY ends the recharge of PROPULSION if it is still running,
+        // the controller has reached it otherwise.
+        pyro_path: &[
+            PyroStep::new(PyroState::READY),
+            PyroStep::new(PyroState::IDLE),
+            PyroStep::new(PyroState::DISCHARGING),
+        ],
+        commit_first: false,
+        target: StateEnum::BURNOUT,
+        lockout: false,
+    },
+    // Fallbacks of the dwell limits
+    Transition {
+        source: StateEnum::READY,
+        trigger: Trigger::Event(StateEvent::Timeout(StateEnum::READY)),
+        guard: None,
+        // The capacitor must not stay charged
+        pyro_path: &[
+            PyroStep::new(PyroState::IDLE),
+            PyroStep::new(PyroState::DISCHARGING),
+        ],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: false,
+    },
+    // No lift-off within the ignition window: the motor may still hang-fire, so the channels stay
+    // off and the capacitor is discharged until the lockout has passed
+    Transition {
+        source: StateEnum::IGNITION,
+        trigger: Trigger::Event(StateEvent::Timeout(StateEnum::IGNITION)),
+        guard: None,
+        pyro_path: &[PyroStep::new(PyroState::DISCHARGING)],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: true,
+    },
+    // Without a reliable link neither the ignition nor the abort may get through, the system
+    // disarms. After the ignition it carries on by itself.
+    Transition {
+        source: StateEnum::READY,
+        trigger: Trigger::Event(StateEvent::LinkDegraded),
+        guard: None,
+        pyro_path: &[
+            PyroStep::new(PyroState::IDLE),
+            PyroStep::new(PyroState::DISCHARGING),
+        ],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: false,
+    },
+    // A channel that loses continuity cannot be fired, the system disarms. The ignition channel
+    // opens once it has fired, so IGNITION carries on.
+    Transition {
+        source: StateEnum::READY,
+        trigger: Trigger::ContinuityLost,
+        guard: None,
+        pyro_path: &[
+            PyroStep::new(PyroState::IDLE),
+            PyroStep::new(PyroState::DISCHARGING),
+        ],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: false,
+    },
+    abort(StateEnum::IDLE, Trigger::Request(StateEnum::ABORT)),
+    abort(StateEnum::IDLE, Trigger::Fault),
+    abort(StateEnum::READY, Trigger::Request(StateEnum::ABORT)),
+    abort(StateEnum::READY, Trigger::Fault),
+    abort(StateEnum::IGNITION, Trigger::Request(StateEnum::ABORT)),
+    abort(StateEnum::IGNITION, Trigger::Fault),
+    // No fault aborts in flight, see `in_flight`
+    abort(StateEnum::PROPULSION, Trigger::Request(StateEnum::ABORT)),
+    abort(StateEnum::BURNOUT, Trigger::Request(StateEnum::ABORT)),
+    // Only an explicit reset leaves ABORT, the capacitor has already been discharged
+    Transition {
+        source: StateEnum::ABORT,
+        trigger: Trigger::Request(StateEnum::IDLE),
+        guard: None,
+        pyro_path: &[],
+        commit_first: false,
+        target: StateEnum::IDLE,
+        lockout: false,
+    },
+];
+
+/// Looks up the transition `trigger` causes from `current`. The first entry whose guard passes
+/// is taken.
+pub fn find_transition(
+    current: StateEnum,
+    trigger: Trigger,
+    conditions: &Conditions,
+) -> Result<&'static Transition, TransitionError> {
+    let mut result = Err(TransitionError::NotAllowed);
+    for transition in TRANSITIONS
+        .iter()
+        .filter(|transition| transition.source == current && transition.trigger.matches(&trigger))
+    {
+        match transition.guard {
+            Some(guard) if !guard(conditions) => result = Err(TransitionError::GuardFailed),
+            _ => return Ok(transition),
+        }
+    }
+    result
+}
+
+/// State requested by `command` from `current`, `None` when the command does not request a state
+/// change from there.
+pub fn command_request(command: Command, current: StateEnum) -> Option<StateEnum> {
+    match command {
+        Command::Arm => Some(StateEnum::READY),
+        Command::Ignite => Some(StateEnum::IGNITION),
+        Command::Abort => Some(StateEnum::ABORT),
+        // Both lead to IDLE, but each only from its own state
+        Command::Disarm if current == StateEnum::READY => Some(StateEnum::IDLE),
+        Command::ResetFault if current == StateEnum::ABORT => Some(StateEnum::IDLE),
+        Command::Disarm | Command::ResetFault | Command::Status => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateTransition;
+
+    const STATES: [StateEnum; 6] = [
+        StateEnum::IDLE,
+        StateEnum::READY,
+        StateEnum::IGNITION,
+        StateEnum::PROPULSION,
+        StateEnum::BURNOUT,
+        StateEnum::ABORT,
+    ];
+
+    const LEGAL: [(StateEnum, StateEnum); 4] = [
+        (StateEnum::IDLE, StateEnum::READY),
+        (StateEnum::READY, StateEnum::IDLE),
+        (StateEnum::READY, StateEnum::IGNITION),
+        (StateEnum::ABORT, StateEnum::IDLE),
+    ];
+
+    const LEGAL_EVENTS: [(StateEnum, StateEvent, StateEnum); 7] = [
+        (
+            StateEnum::IGNITION,
+            StateEvent::Liftoff,
+            StateEnum::PROPULSION,
+        ),
+        (
+            StateEnum::PROPULSION,
+            StateEvent::Burnout,
+            StateEnum::BURNOUT,
+        ),
+        (
+            StateEnum::READY,
+            StateEvent::Timeout(StateEnum::READY),
+            StateEnum::IDLE,
+        ),
+        (
+            StateEnum::IGNITION,
+            StateEvent::Timeout(StateEnum::IGNITION),
+            StateEnum::IDLE,
+        ),
+        (StateEnum::READY, StateEvent::LinkDegraded, StateEnum::IDLE),
+        (
+            StateEnum::READY,
+            StateEvent::ContinuityChanged(PyroChannelName::Pyro1, Continuity::Open),
+            StateEnum::IDLE,
+        ),
+        (
+            StateEnum::READY,
+            StateEvent::ContinuityChanged(PyroChannelName::Ignition, Continuity::Short),
+            StateEnum::IDLE,
+        ),
+    ];
+
+    const ARMED: Conditions = Conditions {
+        continuity_ok: true,
+        abort_reason: None,
+        locked_out: false,
+        link_degraded: false,
+    };
+
+    fn is_legal_request(source: StateEnum, target: StateEnum) -> bool {
+        LEGAL.contains(&(source, target))
+            || (target == StateEnum::ABORT && source != StateEnum::ABORT)
+    }
+
+    #[test]
+    fn legal_requests() {
+        for source in STATES {
+            for target in STATES {
+                if !is_legal_request(source, target) {
+                    continue;
+                }
+                let transition = find_transition(source, Trigger::Request(target), &ARMED).unwrap();
+                assert_eq!(transition.target, target);
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_requests() {
+        for source in STATES {
+            for target in STATES {
+                if is_legal_request(source, target) {
+                    continue;
+                }
+                assert_eq!(
+                    find_transition(source, Trigger::Request(target), &ARMED).err(),
+                    Some(TransitionError::NotAllowed),
+                    "{:?} -> {:?}",
+                    source,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn legal_events() {
+        for (source, event, target) in LEGAL_EVENTS {
+            let transition =
+                find_transition(source, Trigger::Event(event), &Conditions::default()).unwrap();
+            assert_eq!(transition.target, target);
+        }
+    }
+
+    #[test]
+    fn illegal_events() {
+        let mut events = std::vec![
+            StateEvent::ChargeComplete,
+            StateEvent::Liftoff,
+            StateEvent::Burnout,
+            StateEvent::Pyro(PyroState::READY),
+            StateEvent::LinkDegraded,
+            StateEvent::LinkRestored,
+            StateEvent::ContinuityChanged(PyroChannelName::Pyro1, Continuity::Open),
+            StateEvent::ContinuityChanged(PyroChannelName::Pyro1, Continuity::Ok),
+        ];
+        events.extend(STATES.iter().map(|state| StateEvent::Timeout(*state)));
+        for source in STATES {
+            for event in events.iter().copied() {
+                if LEGAL_EVENTS
+                    .iter()
+                    .any(|legal| (legal.0, legal.1) == (source, event))
+                {
+                    continue;
+                }
+                assert_eq!(
+                    find_transition(source, Trigger::Event(event), &ARMED).err(),
+                    Some(TransitionError::NotAllowed),
+                    "{:?} on {:?}",
+                    source,
+                    event
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ignition_fires_ignition_channel() {
+        let transition = find_transition(
+            StateEnum::READY,
+            Trigger::Request(StateEnum::IGNITION),
+            &ARMED,
+        )
+        .unwrap();
+        let path: std::vec::Vec<PyroState> =
+            transition.pyro_path.iter().map(|step| step.state).collect();
+        assert_eq!(path, [PyroState::FIRING(PyroChannelName::Ignition)]);
+    }
+
+    #[test]
+    fn propulsion_holds_charge() {
+        let transition = find_transition(
+            StateEnum::IGNITION,
+            Trigger::Event(StateEvent::Liftoff),
+            &Conditions::default(),
+        )
+        .unwrap();
+        let mut path: StateTransition<PyroState, 5> = StateTransition::builder()
+            .path(transition.pyro_path)
+            .build()
+            .unwrap();
+        path.start(0);
+        path.next(0).unwrap();
+        path.record(StateEvent::ChargeComplete);
+        assert!(!path.guard_passes(PROPULSION_CHARGE_HOLD_MS - 1, &Conditions::default()));
+        assert!(path.guard_passes(PROPULSION_CHARGE_HOLD_MS, &Conditions::default()));
+    }
+
+    #[test]
+    fn fallbacks_discharge() {
+        for state in [StateEnum::READY, StateEnum::IGNITION, StateEnum::PROPULSION] {
+            let event = if state == StateEnum::PROPULSION {
+                StateEvent::Burnout
+            } else {
+                StateEvent::Timeout(state)
+            };
+            let transition =
+                find_transition(state, Trigger::Event(event), &Conditions::default()).unwrap();
+            assert_eq!(
+                transition.pyro_path.last().map(|step| step.state),
+                Some(PyroState::DISCHARGING)
+            );
+            // Only a failed ignition can leave a hang-fire behind
+            assert_eq!(transition.lockout, state == StateEnum::IGNITION);
+        }
+    }
+
+    #[test]
+    fn pyro_paths_are_valid() {
+        for transition in TRANSITIONS {
+            assert!(
+                StateTransition::<PyroState, 5>::builder()
+                    .path(transition.pyro_path)
+                    .build()
+                    .is_ok(),
+                "{:?} -> {:?}",
+                transition.source,
+                transition.target
+            );
+        }
+    }
+
+    #[test]
+    fn faults_abort() {
+        let fault = Trigger::Event(StateEvent::Fault(Fault::ChargeFailure));
+        for source in STATES {
+            let result = find_transition(source, fault, &Conditions::default());
+            if source == StateEnum::ABORT || in_flight(source) {
+                assert_eq!(result.err(), Some(TransitionError::NotAllowed));
+            } else {
+                let transition = result.unwrap();
+                assert_eq!(transition.target, StateEnum::ABORT);
+                assert!(transition.pyro_path.is_empty());
+            }
+        }
+        assert_eq!(
+            AbortReason::from_trigger(&fault),
+            AbortReason::Fault(Fault::ChargeFailure)
+        );
+        assert_eq!(
+            AbortReason::from_trigger(&Trigger::Request(StateEnum::ABORT)),
+            AbortReason::Command
+        );
+    }
+
+    #[test]
+    fn dwell_limits() {
+        let limits = [DwellLimit {
+            state: StateEnum::READY,
+            max_ms: 1000,
+        }];
+        assert_eq!(max_dwell_ms(&limits, StateEnum::READY), Some(1000));
+        assert_eq!(max_dwell_ms(&limits, StateEnum::IDLE), None);
+    }
+
+    #[test]
+    fn arming_requires_continuity() {
+        let no_continuity = Conditions::default();
+        for (source, target) in [
+            (StateEnum::IDLE, StateEnum::READY),
+            (StateEnum::READY, StateEnum::IGNITION),
+        ] {
+            assert_eq!(
+                find_transition(source, Trigger::Request(target), &no_continuity).err(),
+                Some(TransitionError::GuardFailed)
+            );
+        }
+    }
+
+    #[test]
+    fn degraded_link_holds_arming() {
+        let transition = find_transition(
+            StateEnum::IDLE,
+            Trigger::Request(StateEnum::READY),
+            &ARMED,
+        )
+        .unwrap();
+        let mut path: StateTransition<PyroState, 5> = StateTransition::builder()
+            .path(transition.pyro_path)
+            .build()
+            .unwrap();
+        path.start(0);
+        path.next(0).unwrap();
+        path.record(StateEvent::ChargeComplete);
+        let link_degraded = Conditions {
+            link_degraded: true,
+            ..ARMED
+        };
+        assert!(!path.guard_passes(100, &link_degraded));
+        assert!(path.guard_passes(200, &ARMED));
+    }
+
+    #[test]
+    fn lockout_refuses_arming() {
+        let locked_out = Conditions {
+            locked_out: true,
+            ..ARMED
+        };
+        assert_eq!(
+            find_transition(
+                StateEnum::IDLE,
+                Trigger::Request(StateEnum::READY),
+                &locked_out
+            )
+            .err(),
+            Some(TransitionError::GuardFailed)
+        );
+        let link_degraded = Conditions {
+            link_degraded: true,
+            ..ARMED
+        };
+        assert_eq!(
+            find_transition(
+                StateEnum::IDLE,
+                Trigger::Request(StateEnum::READY),
+                &link_degraded
+            )
+            .err(),
+            Some(TransitionError::GuardFailed)
+        );
+        assert!(find_transition(
+            StateEnum::IDLE,
+            Trigger::Request(StateEnum::ABORT),
+            &locked_out
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            command_request(Command::Arm, StateEnum::IDLE),
+            Some(StateEnum::READY)
+        );
+        assert_eq!(
+            command_request(Command::Disarm, StateEnum::READY),
+            Some(StateEnum::IDLE)
+        );
+        // Disarming must not reset an abort, and the reset must not disarm
+        assert_eq!(command_request(Command::Disarm, StateEnum::ABORT), None);
+        assert_eq!(command_request(Command::ResetFault, StateEnum::READY), None);
+        assert_eq!(
+            command_request(Command::ResetFault, StateEnum::ABORT),
+            Some(StateEnum::IDLE)
+        );
+        assert_eq!(command_request(Command::Status, StateEnum::IDLE), None);
+
+        let disarm =
+            find_transition(StateEnum::READY, Trigger::Request(StateEnum::IDLE), &ARMED).unwrap();
+        assert_eq!(
+            disarm.pyro_path.last().map(|step| step.state),
+            Some(PyroState::DISCHARGING)
+        );
+    }
+}
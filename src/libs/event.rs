@@ -1,12 +1,111 @@
-use crate::pyro::PyroState;
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+
+use crate::{
+    aero::{event_code, message, EVENT_ID},
+    continuity::Continuity,
+    engine::AbortReason,
+    pyro::{PyroChannelName, PyroError, PyroState},
+    pyro_log::channel_code,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Event {
     StateInfo(StateEvent),
+    Pyro(PyroEvent),
+    Fault(Fault),
+}
+
+impl Event {
+    /// The event as the state machine sees it, `None` for events it does not act on.
+    pub fn state_event(&self) -> Option<StateEvent> {
+        match self {
+            Event::StateInfo(event) => Some(*event),
+            Event::Fault(fault) => Some(StateEvent::Fault(*fault)),
+            Event::Pyro(_) => None,
+        }
+    }
+
+    /// CAN report of `count` events that could not be handed to the state handler.
+    pub fn dropped_report(count: u32) -> CANAerospaceMessage {
+        message(EVENT_ID, event_code::EVENTS_DROPPED, DataType::ULONG(count))
+    }
+
+    /// CAN report of the event, `None` for events that are not reported.
+    pub fn report(&self) -> Option<CANAerospaceMessage> {
+        let byte = |data: u8| DataType::UCHAR4([data, 0, 0, 0]);
+        let (code, data) = match self {
+            Event::StateInfo(StateEvent::Liftoff) => (event_code::LIFTOFF, DataType::NODATA),
+            Event::StateInfo(StateEvent::Burnout) => (event_code::BURNOUT, DataType::NODATA),
+            Event::StateInfo(StateEvent::LinkDegraded) => {
+                (event_code::LINK_DEGRADED, DataType::NODATA)
+            }
+            Event::StateInfo(StateEvent::LinkRestored) => {
+                (event_code::LINK_RESTORED, DataType::NODATA)
+            }
+            Event::StateInfo(StateEvent::Timeout(state)) => {
+                (event_code::TIMEOUT, byte(*state as u8))
+            }
+            Event::StateInfo(StateEvent::Aborted(reason)) => {
+                let reason = match reason {
+                    AbortReason::Command => 0,
+                    AbortReason::Fault(Fault::ChargeFailure) => 1,
+                    AbortReason::Fault(Fault::Pyro(_)) => 2,
+                    AbortReason::Fault(Fault::TransitionStuck) => 3,
+                };
+                (event_code::ABORT, byte(reason))
+            }
+            Event::StateInfo(StateEvent::TransitionRejected(state)) => {
+                (event_code::TRANSITION_REJECTED, byte(*state as u8))
+            }
+            Event::StateInfo(StateEvent::ContinuityChanged(channel, continuity)) => (
+                event_code::CONTINUITY_CHANGED,
+                DataType::UCHAR4([channel_code(*channel), continuity.code(), 0, 0]),
+            ),
+            _ => return None,
+        };
+        Some(message(EVENT_ID, code, data))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StateEvent {
     System(crate::StateEnum),
     Pyro(PyroState),
+    /// The firing capacitor has reached its charge threshold.
+    ChargeComplete,
+    /// Acceleration or altitude show that the vehicle has left the pad.
+    Liftoff,
+    /// The acceleration has dropped after the minimum burn time, the motor is burnt out.
+    Burnout,
+    /// The system has stayed in the state longer than its dwell limit.
+    Timeout(crate::StateEnum),
+    /// The system has entered ABORT, the pyro controller has been safed.
+    Aborted(AbortReason),
+    /// The pyro path towards the state is invalid, the system stays where it is.
+    TransitionRejected(crate::StateEnum),
+    /// The pyro controller has refused the step, its state did not allow it.
+    StepRejected(PyroState),
+    /// The CAN controller has gone error passive or bus-off, commands may not get through.
+    LinkDegraded,
+    /// The CAN error counters are back below the warning limit.
+    LinkRestored,
+    /// The continuity of the channel has changed while the system is armed.
+    ContinuityChanged(PyroChannelName, Continuity),
+    Fault(Fault),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PyroEvent {
+    /// The fire pulse of the channel has ended and the channel is disabled again.
+    PulseEnded(PyroChannelName),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// The firing capacitor did not reach its charge threshold in time.
+    ChargeFailure,
+    /// The pyro controller failed and has been put into its safe state.
+    Pyro(PyroError),
+    /// A step of the pyro transition did not complete in time.
+    TransitionStuck,
 }
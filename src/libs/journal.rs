@@ -0,0 +1,220 @@
+use core::fmt;
+
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+
+use crate::{
+    aero::{message, service_code},
+    engine::{AbortReason, Trigger},
+    event::{Fault, StateEvent},
+    pyro::PyroState,
+    pyro_log::{channel_code, state_code},
+    record_log::{Record, RecordLog},
+    StateEnum,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JournalTransition {
+    /// System state change, from the first state to the second one
+    System(StateEnum, StateEnum),
+    /// Step of a pyro transition
+    Pyro(PyroState),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JournalResult {
+    Done,
+    /// The trigger does not lead anywhere from the current state
+    NotAllowed,
+    /// The guard of the transition did not pass
+    GuardFailed,
+    /// The pyro path of the transition is invalid
+    PathRejected,
+    /// The pyro controller refused the step or failed, see the pyro log
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JournalEntry {
+    /// Milliseconds since boot
+    pub timestamp_ms: u32,
+    pub transition: JournalTransition,
+    /// `None` when the step was entered on elapsed time or a sensor predicate
+    pub trigger: Option<Trigger>,
+    pub result: JournalResult,
+}
+
+impl JournalEntry {
+    /// Encodes the entry as a timestamp, a transition and a trigger message, all carrying `index`
+    /// as message code.
+    pub fn to_messages(&self, id: u16, index: u8) -> [CANAerospaceMessage; 3] {
+        let (kind, from, to) = match self.transition {
+            JournalTransition::System(from, to) => (0, from as u8, to as u8),
+            JournalTransition::Pyro(state) => match state {
+                PyroState::FIRING(channel) => (1, state_code(state), channel_code(channel)),
+                _ => (1, state_code(state), 0),
+            },
+        };
+        let result = match self.result {
+            JournalResult::Done => 0,
+            JournalResult::NotAllowed => 1,
+            JournalResult::GuardFailed => 2,
+            JournalResult::PathRejected => 3,
+            JournalResult::Failed => 4,
+        };
+        let (trigger, code, data) = match self.trigger {
+            None => (0, 0, 0),
+            Some(Trigger::Request(state)) => (1, state as u8, 0),
+            Some(Trigger::Event(event)) => {
+                let (code, data) = event_code(&event);
+                (2, code, data)
+            }
+            Some(Trigger::Fault) => (3, 0, 0),
+            Some(Trigger::ContinuityLost) => (4, 0, 0),
+        };
+        [
+            message(id, index, DataType::ULONG(self.timestamp_ms)),
+            message(id, index, DataType::UCHAR4([kind, from, to, result])),
+            message(id, index, DataType::UCHAR4([trigger, code, data, 0])),
+        ]
+    }
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10} ms ", self.timestamp_ms)?;
+        match self.transition {
+            JournalTransition::System(from, to) => write!(f, "{:?} -> {:?}", from, to)?,
+            JournalTransition::Pyro(state) => write!(f, "pyro {:?}", state)?,
+        }
+        match self.trigger {
+            Some(trigger) => write!(f, " on {:?}", trigger)?,
+            None => write!(f, " on guard")?,
+        }
+        write!(f, ": {:?}", self.result)
+    }
+}
+
+/// Returns the event as a code and its data, as sent in the trigger message.
+pub fn event_code(event: &StateEvent) -> (u8, u8) {
+    match event {
+        StateEvent::System(state) => (0, *state as u8),
+        StateEvent::Pyro(state) => (1, state_code(*state)),
+        StateEvent::ChargeComplete => (2, 0),
+        StateEvent::Liftoff => (3, 0),
+        StateEvent::Burnout => (4, 0),
+        StateEvent::Timeout(state) => (5, *state as u8),
+        StateEvent::Aborted(AbortReason::Command) => (6, 0),
+        StateEvent::Aborted(AbortReason::Fault(fault)) => (6, fault_code(fault)),
+        StateEvent::TransitionRejected(state) => (7, *state as u8),
+        StateEvent::Fault(fault) => (8, fault_code(fault)),
+        StateEvent::LinkDegraded => (9, 0),
+        StateEvent::LinkRestored => (10, 0),
+        StateEvent::StepRejected(state) => (11, state_code(*state)),
+        StateEvent::ContinuityChanged(channel, _) => (12, channel_code(*channel)),
+    }
+}
+
+fn fault_code(fault: &Fault) -> u8 {
+    match fault {
+        Fault::ChargeFailure => 1,
+        Fault::Pyro(_) => 2,
+        Fault::TransitionStuck => 3,
+    }
+}
+
+impl Record for JournalEntry {
+    const MESSAGES: usize = 3;
+    const SERVICE_CODE: u8 = service_code::JOURNAL;
+
+    fn message(&self, id: u16, index: u8, part: usize) -> CANAerospaceMessage {
+        self.to_messages(id, index)[part]
+    }
+}
+
+/// Ring buffer of the latest `N` system and pyro transitions, older entries are overwritten.
+pub type Journal<const N: usize> = RecordLog<JournalEntry, N>;
+
+impl<const N: usize> Journal<N> {
+    pub fn record(
+        &mut self,
+        timestamp_ms: u32,
+        transition: JournalTransition,
+        trigger: Option<Trigger>,
+        result: JournalResult,
+    ) -> JournalEntry {
+        let entry = JournalEntry {
+            timestamp_ms,
+            transition,
+            trigger,
+            result,
+        };
+        self.push(entry);
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn encodes_and_prints_entry() {
+        let entry = JournalEntry {
+            timestamp_ms: 1500,
+            transition: JournalTransition::System(StateEnum::IDLE, StateEnum::READY),
+            trigger: Some(Trigger::Request(StateEnum::READY)),
+            result: JournalResult::GuardFailed,
+        };
+        let [timestamp, transition, trigger] = entry.to_messages(2001, 3);
+        assert_eq!(timestamp.data, DataType::ULONG(1500));
+        assert_eq!(transition.header.message_code, 3);
+        assert_eq!(
+            transition.data,
+            DataType::UCHAR4([0, StateEnum::IDLE as u8, StateEnum::READY as u8, 2])
+        );
+        assert_eq!(
+            trigger.data,
+            DataType::UCHAR4([1, StateEnum::READY as u8, 0, 0])
+        );
+        assert_eq!(
+            entry.to_string(),
+            "      1500 ms IDLE -> READY on Request(READY): GuardFailed"
+        );
+    }
+
+    #[test]
+    fn dump() {
+        let mut journal = Journal::<2>::new();
+        journal.record(
+            10,
+            JournalTransition::Pyro(PyroState::CHARGING),
+            None,
+            JournalResult::Done,
+        );
+        journal.record(
+            20,
+            JournalTransition::Pyro(PyroState::READY),
+            Some(Trigger::Event(StateEvent::ChargeComplete)),
+            JournalResult::Done,
+        );
+        journal.record(
+            30,
+            JournalTransition::System(StateEnum::IDLE, StateEnum::READY),
+            Some(Trigger::Request(StateEnum::READY)),
+            JournalResult::Done,
+        );
+        assert_eq!(journal.get(0).map(|entry| entry.timestamp_ms), Some(20));
+
+        let messages: std::vec::Vec<_> = (0..)
+            .map_while(|index| journal.dump_message(2001, index))
+            .collect();
+        assert_eq!(messages.len(), 7);
+        assert_eq!(messages[2].data, DataType::UCHAR4([2, 2, 0, 0]));
+        assert_eq!(messages[6].data, DataType::NODATA);
+        assert_eq!(messages[6].header.message_code, 2);
+        assert!(messages
+            .iter()
+            .all(|message| message.header.service_code == service_code::JOURNAL));
+    }
+}
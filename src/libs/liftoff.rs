@@ -0,0 +1,104 @@
+use crate::aero::FlightData;
+
+/// Parameters of the lift-off detection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiftoffConfig {
+    /// Longitudinal acceleration [g] the vehicle is considered accelerating above
+    pub acceleration: f32,
+    /// Time the acceleration has to stay above the threshold
+    pub hold_ms: u32,
+    /// Altitude gain [m] over the first altitude sample that counts as lift-off on its own
+    pub altitude_gain: f32,
+}
+
+/// Detects the lift-off during ignition from the acceleration and the altitude, whichever
+/// confirms it first.
+pub struct LiftoffDetector {
+    config: LiftoffConfig,
+    above_since: Option<u32>,
+    ground_altitude: Option<f32>,
+    detected: bool,
+}
+
+impl LiftoffDetector {
+    pub fn new(config: LiftoffConfig) -> Self {
+        Self {
+            config,
+            above_since: None,
+            ground_altitude: None,
+            detected: false,
+        }
+    }
+
+    pub fn config(&self) -> LiftoffConfig {
+        self.config
+    }
+
+    /// Evaluates a flight data sample, the first altitude sample is taken as the pad altitude.
+    ///
+    /// Returns `true` once, when the lift-off is detected.
+    pub fn update(&mut self, now_ms: u32, data: FlightData) -> bool {
+        if self.detected {
+            return false;
+        }
+        self.detected = match data {
+            FlightData::Acceleration(acceleration) => {
+                if acceleration < self.config.acceleration {
+                    self.above_since = None;
+                    false
+                } else {
+                    let above_since = *self.above_since.get_or_insert(now_ms);
+                    now_ms.wrapping_sub(above_since) >= self.config.hold_ms
+                }
+            }
+            FlightData::Altitude(altitude) => {
+                let ground = *self.ground_altitude.get_or_insert(altitude);
+                altitude - ground >= self.config.altitude_gain
+            }
+        };
+        self.detected
+    }
+
+    /// Prepares the detector for the next ignition.
+    pub fn reset(&mut self) {
+        self.above_since = None;
+        self.ground_altitude = None;
+        self.detected = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: LiftoffConfig = LiftoffConfig {
+        acceleration: 2.0,
+        hold_ms: 100,
+        altitude_gain: 10.0,
+    };
+
+    #[test]
+    fn detects_sustained_acceleration_once() {
+        let mut detector = LiftoffDetector::new(CONFIG);
+        assert!(!detector.update(0, FlightData::Acceleration(1.0)));
+        assert!(!detector.update(10, FlightData::Acceleration(3.0)));
+        // A single sample below the threshold restarts the hold time
+        assert!(!detector.update(50, FlightData::Acceleration(1.5)));
+        assert!(!detector.update(60, FlightData::Acceleration(3.0)));
+        assert!(!detector.update(159, FlightData::Acceleration(3.0)));
+        assert!(detector.update(160, FlightData::Acceleration(3.0)));
+        assert!(!detector.update(170, FlightData::Acceleration(3.0)));
+    }
+
+    #[test]
+    fn detects_altitude_gain() {
+        let mut detector = LiftoffDetector::new(CONFIG);
+        assert!(!detector.update(0, FlightData::Altitude(250.0)));
+        assert!(!detector.update(100, FlightData::Altitude(259.0)));
+        assert!(detector.update(200, FlightData::Altitude(260.5)));
+
+        detector.reset();
+        assert!(!detector.update(300, FlightData::Altitude(260.5)));
+        assert!(!detector.update(400, FlightData::Altitude(265.0)));
+    }
+}
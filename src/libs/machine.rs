@@ -0,0 +1,547 @@
+//! Decisions of the state handler, shared by the firmware tasks and the host simulator.
+
+use crate::{
+    engine::{find_transition, in_flight, AbortReason, Conditions, TransitionError, Trigger},
+    event::{Event, Fault, StateEvent},
+    journal::{JournalResult, JournalTransition},
+    pyro::{PyroError, PyroState},
+    sequence::{PyroSequence, SequenceRuntime, SequenceUpdate},
+    StateEnum,
+};
+
+/// What the state machine needs from its surroundings on top of running the pyro sequence.
+pub trait MachineRuntime: SequenceRuntime {
+    /// The current system state.
+    fn state(&self) -> StateEnum;
+    /// Makes `state` the current system state and arms its dwell limit in place of the previous
+    /// state's.
+    fn enter(&mut self, state: StateEnum);
+    fn record(
+        &mut self,
+        transition: JournalTransition,
+        trigger: Option<Trigger>,
+        result: JournalResult,
+    );
+    /// Safes the pyro controller at once, whatever it is doing.
+    fn abort_pyro(&mut self);
+    /// Starts the hang-fire lockout over, `locked_out` is cleared once it has passed.
+    fn start_lockout(&mut self);
+    /// Sends the CAN report of the event, if it has one, see [`Event::report`].
+    fn report(&mut self, event: &Event);
+}
+
+/// Reports `event` and handles it, or the request for `new_state`, see [`handle`]. The events
+/// raised on the way are reported and handled in turn before it returns, they never wait in a
+/// queue.
+pub fn process(
+    runtime: &mut impl MachineRuntime,
+    sequence: &mut PyroSequence,
+    conditions: &mut Conditions,
+    mut event: Option<Event>,
+    mut new_state: Option<StateEnum>,
+) {
+    // Every event raises at most one more, the chain ends with the report of an abort at the latest
+    loop {
+        let state_event = match event {
+            Some(event) => {
+                runtime.report(&event);
+                match event.state_event() {
+                    Some(state_event) => Some(state_event),
+                    None => return,
+                }
+            }
+            None => None,
+        };
+        let current = runtime.state();
+        event = handle(
+            runtime,
+            sequence,
+            conditions,
+            current,
+            state_event,
+            new_state.take(),
+        );
+        if event.is_none() {
+            return;
+        }
+    }
+}
+
+/// Takes the transition `event` or the request for `new_state` leads to from `current`. Without
+/// either, the guard of the waiting pyro step is re-evaluated. Returns the event raised on the
+/// way, e.g. the report of an abort, it has to be handled as well.
+pub fn handle(
+    runtime: &mut impl MachineRuntime,
+    sequence: &mut PyroSequence,
+    conditions: &mut Conditions,
+    current: StateEnum,
+    event: Option<StateEvent>,
+    new_state: Option<StateEnum>,
+) -> Option<Event> {
+    let trigger = match (new_state, event) {
+        (Some(state), _) => Some(Trigger::Request(state)),
+        (None, Some(event)) => Some(Trigger::Event(event)),
+        (None, None) => None,
+    };
+    let lookup = trigger.map(|trigger| find_transition(current, trigger, conditions));
+    let transition = lookup.and_then(|lookup| lookup.ok());
+
+    // Refused requests are journaled, events without a transition are routine
+    if let (Some(Trigger::Request(state)), Some(Err(e))) = (trigger, lookup) {
+        let result = match e {
+            TransitionError::NotAllowed => JournalResult::NotAllowed,
+            TransitionError::GuardFailed => JournalResult::GuardFailed,
+        };
+        runtime.record(JournalTransition::System(current, state), trigger, result);
+    }
+
+    // ABORT preempts everything, including a transition in progress
+    if let (Some(trigger), Some(transition)) = (trigger, transition) {
+        if transition.target == StateEnum::ABORT {
+            sequence.cancel(runtime);
+            runtime.abort_pyro();
+            commit(runtime, current, StateEnum::ABORT, trigger);
+
+            let reason = AbortReason::from_trigger(&trigger);
+            conditions.abort_reason = Some(reason);
+            return Some(Event::StateInfo(StateEvent::Aborted(reason)));
+        }
+    }
+
+    // A fault in flight does not abort, the actions of the state are given up and the pyro
+    // controller is left as it is
+    let fault = matches!(event, Some(StateEvent::Fault(_)));
+    if fault && in_flight(current) && sequence.committed() {
+        sequence.cancel(runtime);
+    }
+
+    // A pyro path towards a new state takes every other trigger, only ABORT preempts it. The pyro
+    // actions of a committed state give way to any transition from that state.
+    if sequence.in_progress() && !(sequence.committed() && transition.is_some()) {
+        match sequence.update(runtime, event, conditions) {
+            Ok(SequenceUpdate::Pending) | Ok(SequenceUpdate::Finished) => None,
+            Ok(SequenceUpdate::Complete(target, trigger)) => {
+                commit(runtime, current, target, trigger);
+                None
+            }
+            Ok(SequenceUpdate::Rejected(target, trigger)) => {
+                // The system stays where it is, but the controller may be left part way along the
+                // path and is safed.
+                runtime.abort_pyro();
+                runtime.record(
+                    JournalTransition::System(current, target),
+                    Some(trigger),
+                    JournalResult::Failed,
+                );
+                Some(Event::StateInfo(StateEvent::TransitionRejected(target)))
+            }
+            // The sequence has been given up, it must not be taken as complete
+            Err(_) => Some(Event::Fault(Fault::TransitionStuck)),
+        }
+    } else if let (Some(trigger), Some(transition)) = (trigger, transition) {
+        // Whatever is left of the actions of the current state, the transition takes over the
+        // controller
+        if sequence.in_progress() {
+            sequence.cancel(runtime);
+        }
+        if current == StateEnum::ABORT {
+            // Reset, the system may be armed again
+            conditions.abort_reason = None;
+        }
+        if transition.lockout {
+            // The motor may still ignite late, nobody may re-arm until the lockout has passed
+            conditions.locked_out = true;
+            runtime.start_lockout();
+        }
+        if transition.pyro_path.is_empty() || transition.commit_first {
+            commit(runtime, current, transition.target, trigger);
+        }
+        if transition.pyro_path.is_empty() {
+            return None;
+        }
+        let started = if transition.commit_first {
+            sequence.start_committed(runtime, transition.pyro_path, transition.target, trigger)
+        } else {
+            sequence.start(runtime, transition.pyro_path, transition.target, trigger)
+        };
+        if started.is_err() {
+            runtime.record(
+                JournalTransition::System(current, transition.target),
+                Some(trigger),
+                JournalResult::PathRejected,
+            );
+            return Some(Event::StateInfo(StateEvent::TransitionRejected(
+                transition.target,
+            )));
+        }
+        None
+    } else {
+        // Triggers without a transition from the current state are ignored
+        None
+    }
+}
+
+/// Event reporting the outcome of a pyro step to the state handler. A refused step leaves the pins
+/// untouched, on pin errors the controller has already safed itself and the fault is reported.
+pub fn step_event(state: PyroState, result: Result<bool, PyroError>) -> Event {
+    match result {
+        Ok(_) => Event::StateInfo(StateEvent::Pyro(state)),
+        Err(PyroError::StateChangeError(_)) => Event::StateInfo(StateEvent::StepRejected(state)),
+        Err(e) => Event::Fault(Fault::Pyro(e)),
+    }
+}
+
+fn commit(
+    runtime: &mut impl MachineRuntime,
+    current: StateEnum,
+    state: StateEnum,
+    trigger: Trigger,
+) {
+    runtime.enter(state);
+    runtime.record(
+        JournalTransition::System(current, state),
+        Some(trigger),
+        JournalResult::Done,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec as StdVec;
+
+    #[derive(Default)]
+    struct MockRuntime {
+        state: Option<StateEnum>,
+        spawned: StdVec<PyroState>,
+        journal: StdVec<(JournalTransition, JournalResult)>,
+        reported: StdVec<Event>,
+        pyro_aborted: bool,
+        lockouts: u32,
+    }
+
+    impl SequenceRuntime for MockRuntime {
+        fn now_ms(&self) -> u32 {
+            0
+        }
+
+        fn spawn_step(&mut self, step: PyroState, _trigger: Option<Trigger>) -> bool {
+            self.spawned.push(step);
+            true
+        }
+
+        fn arm_watchdog(&mut self) {}
+
+        fn cancel_watchdog(&mut self) {}
+
+        fn schedule_poll(&mut self, _after_ms: u32) {}
+
+        fn cancel_poll(&mut self) {}
+    }
+
+    impl MachineRuntime for MockRuntime {
+        fn state(&self) -> StateEnum {
+            self.state.unwrap_or(StateEnum::IDLE)
+        }
+
+        fn enter(&mut self, state: StateEnum) {
+            self.state = Some(state);
+        }
+
+        fn record(
+            &mut self,
+            transition: JournalTransition,
+            _trigger: Option<Trigger>,
+            result: JournalResult,
+        ) {
+            self.journal.push((transition, result));
+        }
+
+        fn abort_pyro(&mut self) {
+            self.pyro_aborted = true;
+        }
+
+        fn start_lockout(&mut self) {
+            self.lockouts += 1;
+        }
+
+        fn report(&mut self, event: &Event) {
+            self.reported.push(*event);
+        }
+    }
+
+    #[test]
+    fn abort_preempts_sequence() {
+        let mut runtime = MockRuntime::default();
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions {
+            continuity_ok: true,
+            ..Conditions::default()
+        };
+        let ready = Some(StateEnum::READY);
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IDLE,
+            None,
+            ready,
+        );
+        assert!(sequence.in_progress());
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+
+        let abort = Some(StateEnum::ABORT);
+        let raised = handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IDLE,
+            None,
+            abort,
+        );
+        assert!(!sequence.in_progress());
+        assert!(runtime.pyro_aborted);
+        assert_eq!(runtime.state, Some(StateEnum::ABORT));
+        assert_eq!(conditions.abort_reason, Some(AbortReason::Command));
+        assert_eq!(
+            raised,
+            Some(Event::StateInfo(StateEvent::Aborted(AbortReason::Command)))
+        );
+    }
+
+    #[test]
+    fn raised_events_are_processed() {
+        let mut runtime = MockRuntime::default();
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions {
+            continuity_ok: true,
+            ..Conditions::default()
+        };
+        let ready = Some(StateEnum::READY);
+        process(&mut runtime, &mut sequence, &mut conditions, None, ready);
+        assert!(sequence.in_progress());
+
+        let rejected = Event::StateInfo(StateEvent::StepRejected(PyroState::CHARGING));
+        process(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            Some(rejected),
+            None,
+        );
+        assert!(!sequence.in_progress());
+        assert!(runtime.pyro_aborted);
+        assert_eq!(
+            runtime.reported,
+            [
+                rejected,
+                Event::StateInfo(StateEvent::TransitionRejected(StateEnum::READY))
+            ]
+        );
+
+        // A fault aborts, the abort is reported right after it
+        let fault = Event::Fault(Fault::ChargeFailure);
+        process(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            Some(fault),
+            None,
+        );
+        assert_eq!(runtime.state, Some(StateEnum::ABORT));
+        assert_eq!(
+            runtime.reported[2..],
+            [
+                fault,
+                Event::StateInfo(StateEvent::Aborted(AbortReason::Fault(
+                    Fault::ChargeFailure
+                )))
+            ]
+        );
+    }
+
+    #[test]
+    fn refused_request_is_journaled() {
+        let mut runtime = MockRuntime::default();
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions::default();
+        let ready = Some(StateEnum::READY);
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IDLE,
+            None,
+            ready,
+        );
+        assert!(!sequence.in_progress());
+        assert_eq!(
+            runtime.journal,
+            [(
+                JournalTransition::System(StateEnum::IDLE, StateEnum::READY),
+                JournalResult::GuardFailed
+            )]
+        );
+    }
+
+    #[test]
+    fn failed_ignition_starts_lockout() {
+        let mut runtime = MockRuntime::default();
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions::default();
+        let timeout = Some(StateEvent::Timeout(StateEnum::IGNITION));
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IGNITION,
+            timeout,
+            None,
+        );
+        assert!(conditions.locked_out);
+        assert_eq!(runtime.lockouts, 1);
+        assert_eq!(runtime.spawned, [PyroState::DISCHARGING]);
+
+        let discharged = Some(StateEvent::Pyro(PyroState::DISCHARGING));
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IGNITION,
+            discharged,
+            None,
+        );
+        assert_eq!(runtime.state, Some(StateEnum::IDLE));
+    }
+
+    #[test]
+    fn liftoff_commits_propulsion_first() {
+        let mut runtime = MockRuntime::default();
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions::default();
+        let liftoff = Some(StateEvent::Liftoff);
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::IGNITION,
+            liftoff,
+            None,
+        );
+        assert_eq!(runtime.state, Some(StateEnum::PROPULSION));
+        assert!(sequence.committed());
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+
+        // The recharge does not hold back the events of PROPULSION
+        let timeout = Some(StateEvent::Timeout(StateEnum::IGNITION));
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::PROPULSION,
+            timeout,
+            None,
+        );
+        assert_eq!(runtime.state, Some(StateEnum::PROPULSION));
+        assert!(sequence.in_progress());
+
+        let burnout = Some(StateEvent::Burnout);
+        handle(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            StateEnum::PROPULSION,
+            burnout,
+            None,
+        );
+        assert!(!sequence.committed());
+        assert_eq!(runtime.spawned, [PyroState::CHARGING, PyroState::READY]);
+
+        for step in [PyroState::READY, PyroState::IDLE, PyroState::DISCHARGING] {
+            handle(
+                &mut runtime,
+                &mut sequence,
+                &mut conditions,
+                StateEnum::PROPULSION,
+                Some(StateEvent::Pyro(step)),
+                None,
+            );
+        }
+        assert_eq!(runtime.state, Some(StateEnum::BURNOUT));
+        assert_eq!(
+            runtime.journal.last(),
+            Some(&(
+                JournalTransition::System(StateEnum::PROPULSION, StateEnum::BURNOUT),
+                JournalResult::Done
+            ))
+        );
+    }
+
+    #[test]
+    fn in_flight_faults_do_not_abort() {
+        let mut runtime = MockRuntime {
+            state: Some(StateEnum::IGNITION),
+            ..MockRuntime::default()
+        };
+        let mut sequence = PyroSequence::new();
+        let mut conditions = Conditions::default();
+        let liftoff = Event::StateInfo(StateEvent::Liftoff);
+        process(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            Some(liftoff),
+            None,
+        );
+        assert!(sequence.committed());
+
+        for fault in [
+            Fault::ChargeFailure,
+            Fault::Pyro(PyroError::PyroChannelError),
+            Fault::TransitionStuck,
+        ] {
+            process(
+                &mut runtime,
+                &mut sequence,
+                &mut conditions,
+                Some(Event::Fault(fault)),
+                None,
+            );
+            assert_eq!(runtime.state, Some(StateEnum::PROPULSION));
+            assert!(!runtime.pyro_aborted);
+            assert_eq!(conditions.abort_reason, None);
+            assert_eq!(runtime.reported.last(), Some(&Event::Fault(fault)));
+        }
+        // The recharge has been given up
+        assert!(!sequence.in_progress());
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+
+        // The commanded abort still applies
+        process(
+            &mut runtime,
+            &mut sequence,
+            &mut conditions,
+            None,
+            Some(StateEnum::ABORT),
+        );
+        assert_eq!(runtime.state, Some(StateEnum::ABORT));
+        assert!(runtime.pyro_aborted);
+    }
+
+    #[test]
+    fn step_events() {
+        let ready = PyroState::READY;
+        assert_eq!(
+            step_event(ready, Ok(true)),
+            Event::StateInfo(StateEvent::Pyro(ready))
+        );
+        assert_eq!(
+            step_event(ready, Err(PyroError::StateChangeError(PyroState::IDLE))),
+            Event::StateInfo(StateEvent::StepRejected(ready))
+        );
+        assert_eq!(
+            step_event(ready, Err(PyroError::PyroChannelError)),
+            Event::Fault(Fault::Pyro(PyroError::PyroChannelError))
+        );
+    }
+}
@@ -1,6 +1,21 @@
 #![no_std]
 
+pub mod aero;
+pub mod board;
+pub mod burnout;
+pub mod can_health;
+pub mod charge;
+pub mod config;
+pub mod continuity;
+pub mod engine;
 pub mod event;
+pub mod journal;
+pub mod liftoff;
+pub mod machine;
 pub mod pin;
 pub mod pyro;
+pub mod pyro_log;
+pub mod record_log;
+pub mod sequence;
 pub mod state;
+pub mod telemetry;
@@ -1,10 +1,24 @@
 use embedded_hal::digital::v2::OutputPin;
-use stm32f1xx_hal::gpio::{self, ErasedPin, OpenDrain, PushPull};
 
-pub type PINErasedPP = Output<ErasedPin<gpio::Output<PushPull>>, false>;
-pub type PINErasedPPInv = Output<ErasedPin<gpio::Output<PushPull>>, true>;
-pub type PINErasedOD = Output<ErasedPin<gpio::Output<OpenDrain>>, false>;
-pub type PINErasedODInv = Output<ErasedPin<gpio::Output<OpenDrain>>, true>;
+#[cfg(target_os = "none")]
+pub use stm32::*;
+
+/// Pins of the STM32F1, the HAL is only built for the board so that the rest of the library can be
+/// tested on the host.
+#[cfg(target_os = "none")]
+mod stm32 {
+    use stm32f1xx_hal::gpio::{self, ErasedPin, OpenDrain, PushPull};
+
+    use super::Output;
+
+    pub type ErasedPP = ErasedPin<gpio::Output<PushPull>>;
+    pub type ErasedOD = ErasedPin<gpio::Output<OpenDrain>>;
+
+    pub type PINErasedPP = Output<ErasedPP, false>;
+    pub type PINErasedPPInv = Output<ErasedPP, true>;
+    pub type PINErasedOD = Output<ErasedOD, false>;
+    pub type PINErasedODInv = Output<ErasedOD, true>;
+}
 
 pub enum PinError {
     OutputError,
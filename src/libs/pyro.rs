@@ -1,9 +1,12 @@
+use embedded_hal::digital::v2::OutputPin;
 use heapless::Vec;
 
 use crate::{
+    continuity::{Continuity, ContinuityThresholds},
+    engine::Conditions,
     event::StateEvent,
-    pin::{PINErasedPP, PINErasedPPInv},
-    state::MovingState,
+    pin::Output,
+    state::{MovingState, StepGuard},
 };
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -16,16 +19,16 @@ pub enum PyroState {
 }
 
 impl MovingState for PyroState {
-    fn get_required_events(&self) -> Vec<StateEvent, 5_usize> {
-        let mut events: Vec<StateEvent, 5> = Vec::new();
+    type Context = Conditions;
+
+    fn guard(&self) -> StepGuard<Conditions> {
         match self {
-            PyroState::IDLE => {}
-            PyroState::CHARGING => {}
-            PyroState::DISCHARGING => {}
-            PyroState::READY => events.push(StateEvent::Pyro(PyroState::CHARGING)).unwrap(),
-            PyroState::FIRING(_) => events.push(StateEvent::Pyro(PyroState::READY)).unwrap(),
+            PyroState::IDLE => StepGuard::Always,
+            PyroState::CHARGING => StepGuard::Always,
+            PyroState::DISCHARGING => StepGuard::Always,
+            PyroState::READY => StepGuard::Event(StateEvent::ChargeComplete),
+            PyroState::FIRING(_) => StepGuard::Event(StateEvent::Pyro(PyroState::READY)),
         }
-        events
     }
 
     fn is_transition_allowed(&self, state: PyroState) -> bool {
@@ -54,24 +57,99 @@ pub enum PyroChannelName {
     Pyro1,
     Pyro2,
     Ignition,
+    /// Only available if a group has been configured for it, see [`PyroController::add_group`]
     Any,
 }
 
-#[derive(Debug, PartialEq)]
+/// Maximum number of groups a controller can hold.
+pub const MAX_GROUPS: usize = 4;
+/// Maximum number of channels in a group.
+pub const MAX_GROUP_CHANNELS: usize = 4;
+
+/// Defines which channels of a group are enabled when the group is fired.
+///
+/// The modes that choose a channel only take one whose continuity is [`Continuity::Ok`], a channel
+/// that has not been measured yet is skipped like an open or shorted one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FireMode {
+    /// All channels at once, e.g. redundant e-matches
+    Simultaneous,
+    /// The first channel with good continuity
+    AnyWithContinuity,
+    /// The channels in the given order, a channel is skipped if its continuity is not good or its
+    /// pin cannot be driven
+    PrimaryWithFallback,
+}
+
+/// Channels fired together whenever the group's name is fired.
+pub struct PyroGroup {
+    pub name: PyroChannelName,
+    pub mode: FireMode,
+    pub channels: Vec<PyroChannelName, MAX_GROUP_CHANNELS>,
+}
+
+/// Default width of a fire pulse, can be changed by [`PyroController::set_fire_pulse_width`].
+pub const DEFAULT_FIRE_PULSE_MS: u32 = 500;
+
+/// A channel activation that has to be ended by [`PyroController::end_pulse`] after `width_ms`.
+///
+/// `id` counts the pulses of a controller, so that ending a pulse that has already been ended
+/// cannot cut a later pulse on the same channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FirePulse {
+    pub channel: PyroChannelName,
+    pub width_ms: u32,
+    pub id: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PyroError {
     ControllerIsFull,
     PyroChannelError,
+    /// No channel or group with the given name has been added
+    UnknownChannel,
+    /// None of the channels of the group can be fired
+    NoChannelAvailable,
+    /// Charge or discharge pin could not be driven
+    CircuitError,
     StateChangeError(PyroState),
 }
 
-pub struct PyroChannel {
+#[derive(Clone, Copy)]
+enum Circuit {
+    Charge,
+    Discharge,
+}
+
+pub struct PyroChannel<IO> {
     pub name: PyroChannelName,
-    pub pin: PINErasedPP,
+    pub pin: Output<IO, false>,
+    continuity: Continuity,
+    active: bool,
 }
 
-impl PyroChannel {
+impl<IO: OutputPin> PyroChannel<IO> {
+    pub fn new(name: PyroChannelName, pin: Output<IO, false>) -> Self {
+        Self {
+            name,
+            pin,
+            continuity: Continuity::Unknown,
+            active: false,
+        }
+    }
+
+    pub fn continuity(&self) -> Continuity {
+        self.continuity
+    }
+
+    /// Returns true if the channel has been enabled and not disabled since.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
     pub fn enable(&mut self) -> Result<(), PyroError> {
         if self.pin.enable().is_ok() {
+            self.active = true;
             Ok(())
         } else {
             Err(PyroError::PyroChannelError)
@@ -79,6 +157,7 @@ impl PyroChannel {
     }
     pub fn disable(&mut self) -> Result<(), PyroError> {
         if self.pin.disable().is_ok() {
+            self.active = false;
             Ok(())
         } else {
             Err(PyroError::PyroChannelError)
@@ -86,26 +165,36 @@ impl PyroChannel {
     }
 }
 
-pub struct PyroController<const N: usize> {
-    charge: PINErasedPP,
-    discharge: PINErasedPPInv,
-    channels: Vec<PyroChannel, N>,
+pub struct PyroController<const N: usize, IO> {
+    charge: Output<IO, false>,
+    discharge: Output<IO, true>,
+    channels: Vec<PyroChannel<IO>, N>,
+    groups: Vec<PyroGroup, MAX_GROUPS>,
     ready: bool,
     state: PyroState,
+    fire_pulse_ms: u32,
+    active_pulse: Option<FirePulse>,
+    pulse_count: u32,
+    continuity_thresholds: ContinuityThresholds,
 }
 
-impl<const N: usize> PyroController<N> {
-    pub fn new(charge: PINErasedPP, discharge: PINErasedPPInv) -> Self {
+impl<const N: usize, IO: OutputPin> PyroController<N, IO> {
+    pub fn new(charge: Output<IO, false>, discharge: Output<IO, true>) -> Self {
         Self {
             charge,
             discharge,
             channels: Vec::new(),
+            groups: Vec::new(),
             ready: false,
             state: PyroState::IDLE,
+            fire_pulse_ms: DEFAULT_FIRE_PULSE_MS,
+            active_pulse: None,
+            pulse_count: 0,
+            continuity_thresholds: ContinuityThresholds::default(),
         }
     }
 
-    pub fn add_channel(&mut self, channel: PyroChannel) -> Result<(), PyroError> {
+    pub fn add_channel(&mut self, channel: PyroChannel<IO>) -> Result<(), PyroError> {
         if let Ok(_) = self.channels.push(channel) {
             Ok(())
         } else {
@@ -113,43 +202,219 @@ impl<const N: usize> PyroController<N> {
         }
     }
 
-    pub fn charge(&mut self) {
-        self.disable_all_channels();
-        self.discharge.disable().ok().unwrap();
-        self.charge.enable().ok().unwrap();
+    /// Makes `name` fire the given channels according to `mode`.
+    ///
+    /// The channels have to be added before. A group named after a channel replaces that channel
+    /// when it is fired, e.g. to back up the ignition e-match by another channel.
+    pub fn add_group(
+        &mut self,
+        name: PyroChannelName,
+        mode: FireMode,
+        channels: &[PyroChannelName],
+    ) -> Result<(), PyroError> {
+        if channels.is_empty()
+            || !channels
+                .iter()
+                .all(|name| self.channels.iter().any(|channel| channel.name == *name))
+        {
+            return Err(PyroError::UnknownChannel);
+        }
+        let channels = Vec::from_slice(channels).map_err(|_| PyroError::ControllerIsFull)?;
+        self.groups
+            .push(PyroGroup {
+                name,
+                mode,
+                channels,
+            })
+            .map_err(|_| PyroError::ControllerIsFull)
     }
 
-    pub fn discharge(&mut self) {
-        self.disable_all_channels();
-        self.charge.disable().ok().unwrap();
-        self.discharge.enable().ok().unwrap();
+    pub fn charge(&mut self) -> Result<(), PyroError> {
+        self.drive_circuit([(Circuit::Discharge, false), (Circuit::Charge, true)])
     }
 
-    pub fn continuous_state(&mut self) {
-        self.disable_all_channels();
-        self.charge.enable().ok().unwrap();
-        self.discharge.enable().ok().unwrap();
+    pub fn discharge(&mut self) -> Result<(), PyroError> {
+        self.drive_circuit([(Circuit::Charge, false), (Circuit::Discharge, true)])
     }
 
-    pub fn closed_state(&mut self) {
-        self.disable_all_channels();
-        self.discharge.disable().ok().unwrap();
-        self.charge.disable().ok().unwrap();
+    pub fn continuous_state(&mut self) -> Result<(), PyroError> {
+        self.drive_circuit([(Circuit::Charge, true), (Circuit::Discharge, true)])
     }
 
-    pub fn fire(&mut self, channel_name: PyroChannelName) {
-        for channel in &mut self.channels {
-            if channel.name == channel_name {
-                channel.enable().unwrap();
-                break;
+    pub fn closed_state(&mut self) -> Result<(), PyroError> {
+        self.drive_circuit([(Circuit::Discharge, false), (Circuit::Charge, false)])
+    }
+
+    /// Enables the group or channel with the given name and returns the pulse that has to be
+    /// ended after `width_ms`.
+    pub fn fire(
+        &mut self,
+        channel_name: PyroChannelName,
+        width_ms: u32,
+    ) -> Result<FirePulse, PyroError> {
+        let (mode, channels) = self.resolve(channel_name)?;
+        let result = match mode {
+            FireMode::Simultaneous => channels
+                .iter()
+                .try_for_each(|name| self.enable_channel(*name)),
+            FireMode::AnyWithContinuity => {
+                match channels
+                    .iter()
+                    .find(|name| self.continuity(**name) == Continuity::Ok)
+                {
+                    Some(name) => self.enable_channel(*name),
+                    None => Err(PyroError::NoChannelAvailable),
+                }
             }
+            FireMode::PrimaryWithFallback => self.fire_with_fallback(&channels),
+        };
+        if let Err(e) = result {
+            self.safe_state();
+            return Err(e);
         }
+
+        self.pulse_count = self.pulse_count.wrapping_add(1);
+        let pulse = FirePulse {
+            channel: channel_name,
+            width_ms,
+            id: self.pulse_count,
+        };
+        self.active_pulse = Some(pulse);
+        Ok(pulse)
+    }
+
+    /// Disables the channels of `pulse` if it is still the active pulse.
+    ///
+    /// Returns `Ok(false)` if the pulse has already been ended, e.g. by `charge()` or
+    /// `discharge()`, even if the same channel has been fired again since.
+    pub fn end_pulse(&mut self, pulse: FirePulse) -> Result<bool, PyroError> {
+        match self.active_pulse {
+            Some(active) if active.id == pulse.id => {
+                self.active_pulse = None;
+                for channel in &mut self.channels {
+                    if channel.is_active() && channel.disable().is_err() {
+                        self.safe_state();
+                        return Err(PyroError::PyroChannelError);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Best effort to bring the circuit into a safe configuration: all channels off, charge off
+    /// and discharge on.
+    ///
+    /// Errors are ignored so that as many outputs as possible end up safe. Called on every pin
+    /// error, afterwards the controller is in `DISCHARGING` state.
+    pub fn safe_state(&mut self) {
+        for channel in &mut self.channels {
+            channel.disable().ok();
+        }
+        self.active_pulse = None;
+        self.charge.disable().ok();
+        self.discharge.enable().ok();
+        self.state = PyroState::DISCHARGING;
+    }
+
+    /// Iterates over the channels that are currently enabled.
+    pub fn active_channels(&self) -> impl Iterator<Item = PyroChannelName> + '_ {
+        self.channels
+            .iter()
+            .filter(|channel| channel.is_active())
+            .map(|channel| channel.name)
+    }
+
+    pub fn active_pulse(&self) -> Option<FirePulse> {
+        self.active_pulse
+    }
+
+    pub fn set_fire_pulse_width(&mut self, width_ms: u32) {
+        self.fire_pulse_ms = width_ms;
+    }
+
+    pub fn fire_pulse_width(&self) -> u32 {
+        self.fire_pulse_ms
     }
 
+    /// Moves the controller into `new_state` and drives the pins accordingly.
+    ///
+    /// Returns `Ok(false)` if the controller is already in `new_state`, and
+    /// `Err(PyroError::StateChangeError(current))` if the transition is not allowed.
     pub fn change_state(&mut self, new_state: PyroState) -> Result<bool, PyroError> {
-        // TODO
+        if self.state == new_state {
+            return Ok(false);
+        }
+        if !self.state.is_transition_allowed(new_state) {
+            return Err(PyroError::StateChangeError(self.state));
+        }
+
+        match new_state {
+            PyroState::IDLE => self.continuous_state()?,
+            PyroState::CHARGING => self.charge()?,
+            PyroState::DISCHARGING => self.discharge()?,
+            // Disconnects the connection between battery and discharge circuit
+            PyroState::READY => self.closed_state()?,
+            PyroState::FIRING(channel) => {
+                self.fire(channel, self.fire_pulse_ms)?;
+            }
+        }
         self.state = new_state;
-        Ok(false)
+        Ok(true)
+    }
+
+    /// Returns the last measured continuity of the channel, `Unknown` if there is no such channel.
+    pub fn continuity(&self, channel_name: PyroChannelName) -> Continuity {
+        self.channels
+            .iter()
+            .find(|channel| channel.name == channel_name)
+            .map_or(Continuity::Unknown, |channel| channel.continuity)
+    }
+
+    /// Classifies an analog sense reading of the channel and stores the result.
+    ///
+    /// Returns the new continuity only if it has changed.
+    pub fn update_continuity(
+        &mut self,
+        channel_name: PyroChannelName,
+        reading: u16,
+    ) -> Option<Continuity> {
+        let continuity = Continuity::from_reading(reading, &self.continuity_thresholds);
+        self.set_continuity(channel_name, continuity)
+    }
+
+    /// Stores the continuity of a channel with a digital sense input.
+    ///
+    /// Returns the new continuity only if it has changed.
+    pub fn set_continuity(
+        &mut self,
+        channel_name: PyroChannelName,
+        continuity: Continuity,
+    ) -> Option<Continuity> {
+        let channel = self
+            .channels
+            .iter_mut()
+            .find(|channel| channel.name == channel_name)?;
+        if channel.continuity != continuity {
+            channel.continuity = continuity;
+            Some(continuity)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if every channel has been measured with good continuity.
+    pub fn continuity_ok(&self) -> bool {
+        !self.channels.is_empty()
+            && self
+                .channels
+                .iter()
+                .all(|channel| channel.continuity == Continuity::Ok)
+    }
+
+    pub fn set_continuity_thresholds(&mut self, thresholds: ContinuityThresholds) {
+        self.continuity_thresholds = thresholds;
     }
 
     pub fn is_ready(&self) -> bool {
@@ -164,9 +429,519 @@ impl<const N: usize> PyroController<N> {
         self.state
     }
 
-    fn disable_all_channels(&mut self) {
+    /// Returns how the given name is fired and which channels are involved.
+    fn resolve(
+        &self,
+        name: PyroChannelName,
+    ) -> Result<(FireMode, Vec<PyroChannelName, MAX_GROUP_CHANNELS>), PyroError> {
+        if let Some(group) = self.groups.iter().find(|group| group.name == name) {
+            Ok((group.mode, group.channels.clone()))
+        } else if name != PyroChannelName::Any
+            && self.channels.iter().any(|channel| channel.name == name)
+        {
+            let mut channels = Vec::new();
+            channels.push(name).ok();
+            Ok((FireMode::Simultaneous, channels))
+        } else {
+            Err(PyroError::UnknownChannel)
+        }
+    }
+
+    fn enable_channel(&mut self, name: PyroChannelName) -> Result<(), PyroError> {
+        self.channels
+            .iter_mut()
+            .find(|channel| channel.name == name)
+            .ok_or(PyroError::UnknownChannel)?
+            .enable()
+    }
+
+    fn fire_with_fallback(&mut self, channels: &[PyroChannelName]) -> Result<(), PyroError> {
+        for name in channels {
+            if self.continuity(*name) != Continuity::Ok {
+                continue;
+            }
+            if self.enable_channel(*name).is_ok() {
+                return Ok(());
+            }
+            // The pin of a failed channel is left in an unknown state
+            if let Some(channel) = self
+                .channels
+                .iter_mut()
+                .find(|channel| channel.name == *name)
+            {
+                channel.disable().ok();
+            }
+        }
+        Err(PyroError::NoChannelAvailable)
+    }
+
+    /// Disables every channel even if one of them fails, the last error is returned.
+    fn disable_all_channels(&mut self) -> Result<(), PyroError> {
+        let mut result = Ok(());
         for channel in &mut self.channels {
-            channel.disable().unwrap();
+            if let Err(e) = channel.disable() {
+                result = Err(e);
+            }
+        }
+        self.active_pulse = None;
+        result
+    }
+
+    /// Disables all channels and then drives the charge and discharge pins in the given order.
+    fn drive_circuit(&mut self, steps: [(Circuit, bool); 2]) -> Result<(), PyroError> {
+        let result = self.try_drive_circuit(steps);
+        if result.is_err() {
+            self.safe_state();
+        }
+        result
+    }
+
+    fn try_drive_circuit(&mut self, steps: [(Circuit, bool); 2]) -> Result<(), PyroError> {
+        self.disable_all_channels()?;
+        for (circuit, enable) in steps {
+            let result = match (circuit, enable) {
+                (Circuit::Charge, true) => self.charge.enable(),
+                (Circuit::Charge, false) => self.charge.disable(),
+                (Circuit::Discharge, true) => self.discharge.enable(),
+                (Circuit::Discharge, false) => self.discharge.disable(),
+            };
+            if result.is_err() {
+                return Err(PyroError::CircuitError);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc, vec::Vec as StdVec};
+
+    type PinLog = Rc<RefCell<StdVec<(&'static str, bool)>>>;
+
+    /// Output pin that records every level change into a log shared by all pins.
+    /// Failing pins do not record anything.
+    struct MockPin {
+        name: &'static str,
+        log: PinLog,
+        failing: bool,
+    }
+
+    impl MockPin {
+        fn set(&mut self, high: bool) -> Result<(), ()> {
+            if self.failing {
+                return Err(());
+            }
+            self.log.borrow_mut().push((self.name, high));
+            Ok(())
+        }
+    }
+
+    impl OutputPin for MockPin {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set(false)
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.set(true)
+        }
+    }
+
+    fn pin<const INVERTED: bool>(name: &'static str, log: &PinLog) -> Output<MockPin, INVERTED> {
+        Output::new(MockPin {
+            name,
+            log: log.clone(),
+            failing: false,
+        })
+    }
+
+    /// Builds a controller wired the same way `initialize_pyro_controller` does.
+    fn controller() -> (PyroController<3, MockPin>, PinLog) {
+        controller_with_failing(None)
+    }
+
+    fn controller_with_failing(failing: Option<&str>) -> (PyroController<3, MockPin>, PinLog) {
+        let log = PinLog::default();
+        let mut controller =
+            PyroController::<3, MockPin>::new(pin("charge", &log), pin("discharge", &log));
+        for (name, pin_name) in [
+            (PyroChannelName::Pyro1, "pyro1"),
+            (PyroChannelName::Ignition, "ignition"),
+            (PyroChannelName::Pyro2, "pyro2"),
+        ] {
+            let pin = Output::new(MockPin {
+                name: pin_name,
+                log: log.clone(),
+                failing: failing == Some(pin_name),
+            });
+            controller.add_channel(PyroChannel::new(name, pin)).unwrap();
+        }
+        (controller, log)
+    }
+
+    fn take(log: &PinLog) -> StdVec<(&'static str, bool)> {
+        log.borrow_mut().drain(..).collect()
+    }
+
+    const CHANNELS_OFF: [(&str, bool); 3] =
+        [("pyro1", false), ("ignition", false), ("pyro2", false)];
+
+    fn expected(tail: &[(&'static str, bool)]) -> StdVec<(&'static str, bool)> {
+        let mut sequence = CHANNELS_OFF.to_vec();
+        sequence.extend_from_slice(tail);
+        sequence
+    }
+
+    #[test]
+    fn charge_sequence() {
+        let (mut controller, log) = controller();
+        controller.charge().unwrap();
+        assert_eq!(
+            take(&log),
+            expected(&[("discharge", true), ("charge", true)])
+        );
+    }
+
+    #[test]
+    fn discharge_sequence() {
+        let (mut controller, log) = controller();
+        controller.discharge().unwrap();
+        assert_eq!(
+            take(&log),
+            expected(&[("charge", false), ("discharge", false)])
+        );
+    }
+
+    #[test]
+    fn continuous_state_sequence() {
+        let (mut controller, log) = controller();
+        controller.continuous_state().unwrap();
+        assert_eq!(
+            take(&log),
+            expected(&[("charge", true), ("discharge", false)])
+        );
+    }
+
+    #[test]
+    fn closed_state_sequence() {
+        let (mut controller, log) = controller();
+        controller.closed_state().unwrap();
+        assert_eq!(
+            take(&log),
+            expected(&[("discharge", true), ("charge", false)])
+        );
+    }
+
+    #[test]
+    fn fire_enables_only_the_named_channel() {
+        let (mut controller, log) = controller();
+        controller.fire(PyroChannelName::Ignition, 100).unwrap();
+        assert_eq!(take(&log), vec![("ignition", true)]);
+
+        controller.fire(PyroChannelName::Pyro2, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro2", true)]);
+    }
+
+    #[test]
+    fn end_pulse_disables_the_fired_channel() {
+        let (mut controller, log) = controller();
+        let pulse = controller.fire(PyroChannelName::Pyro1, 250).unwrap();
+        assert_eq!(
+            pulse,
+            FirePulse {
+                channel: PyroChannelName::Pyro1,
+                width_ms: 250,
+                id: 1,
+            }
+        );
+        assert_eq!(controller.active_pulse(), Some(pulse));
+        take(&log);
+
+        let other = FirePulse { id: 2, ..pulse };
+        assert_eq!(controller.end_pulse(other), Ok(false));
+        assert!(take(&log).is_empty());
+
+        assert_eq!(controller.end_pulse(pulse), Ok(true));
+        assert_eq!(take(&log), vec![("pyro1", false)]);
+        assert_eq!(controller.active_pulse(), None);
+        assert_eq!(controller.end_pulse(pulse), Ok(false));
+    }
+
+    #[test]
+    fn stale_pulse_end_keeps_later_pulse() {
+        let (mut controller, log) = controller();
+        let first = controller.fire(PyroChannelName::Pyro1, 250).unwrap();
+        controller.discharge().unwrap();
+        let second = controller.fire(PyroChannelName::Pyro1, 250).unwrap();
+        assert_ne!(first.id, second.id);
+        take(&log);
+
+        assert_eq!(controller.end_pulse(first), Ok(false));
+        assert!(take(&log).is_empty());
+        assert_eq!(controller.active_pulse(), Some(second));
+
+        assert_eq!(controller.end_pulse(second), Ok(true));
+        assert_eq!(take(&log), vec![("pyro1", false)]);
+    }
+
+    #[test]
+    fn discharge_ends_active_pulse() {
+        let (mut controller, _log) = controller();
+        controller.fire(PyroChannelName::Ignition, 100).unwrap();
+        controller.discharge().unwrap();
+        assert_eq!(controller.active_pulse(), None);
+    }
+
+    #[test]
+    fn change_state_drives_pins() {
+        let (mut controller, log) = controller();
+        assert_eq!(controller.change_state(PyroState::CHARGING), Ok(true));
+        assert_eq!(
+            take(&log),
+            expected(&[("discharge", true), ("charge", true)])
+        );
+
+        assert_eq!(controller.change_state(PyroState::CHARGING), Ok(false));
+        assert!(take(&log).is_empty());
+
+        assert_eq!(controller.change_state(PyroState::READY), Ok(true));
+        assert_eq!(
+            take(&log),
+            expected(&[("discharge", true), ("charge", false)])
+        );
+
+        let firing = PyroState::FIRING(PyroChannelName::Pyro1);
+        assert_eq!(controller.change_state(firing), Ok(true));
+        assert_eq!(take(&log), vec![("pyro1", true)]);
+        assert_eq!(controller.get_state(), firing);
+        assert_eq!(
+            controller.active_pulse().map(|pulse| pulse.width_ms),
+            Some(DEFAULT_FIRE_PULSE_MS)
+        );
+    }
+
+    #[test]
+    fn change_state_rejects_illegal_transition() {
+        let (mut controller, log) = controller();
+        let firing = PyroState::FIRING(PyroChannelName::Ignition);
+        assert_eq!(
+            controller.change_state(firing),
+            Err(PyroError::StateChangeError(PyroState::IDLE))
+        );
+        assert!(take(&log).is_empty());
+        assert_eq!(controller.get_state(), PyroState::IDLE);
+    }
+
+    #[test]
+    fn continuity_is_tracked_per_channel() {
+        let (mut controller, _log) = controller();
+        assert_eq!(
+            controller.continuity(PyroChannelName::Pyro1),
+            Continuity::Unknown
+        );
+        assert!(!controller.continuity_ok());
+
+        assert_eq!(
+            controller.update_continuity(PyroChannelName::Pyro1, 2000),
+            Some(Continuity::Ok)
+        );
+        assert_eq!(
+            controller.update_continuity(PyroChannelName::Pyro1, 2100),
+            None
+        );
+        assert_eq!(
+            controller.update_continuity(PyroChannelName::Ignition, 4095),
+            Some(Continuity::Open)
+        );
+        assert_eq!(
+            controller.update_continuity(PyroChannelName::Pyro2, 10),
+            Some(Continuity::Short)
+        );
+        assert_eq!(
+            controller.continuity(PyroChannelName::Pyro1),
+            Continuity::Ok
+        );
+        assert!(!controller.continuity_ok());
+
+        controller.update_continuity(PyroChannelName::Ignition, 1500);
+        controller.update_continuity(PyroChannelName::Pyro2, 1500);
+        assert!(controller.continuity_ok());
+
+        assert_eq!(
+            controller.update_continuity(PyroChannelName::Any, 2000),
+            None
+        );
+    }
+
+    #[test]
+    fn pin_error_leaves_circuit_safe() {
+        let (mut controller, log) = controller_with_failing(Some("pyro2"));
+
+        assert_eq!(controller.charge(), Err(PyroError::PyroChannelError));
+        assert_eq!(
+            take(&log),
+            vec![
+                ("pyro1", false),
+                ("ignition", false),
+                ("pyro1", false),
+                ("ignition", false),
+                ("charge", false),
+                ("discharge", false),
+            ]
+        );
+        assert_eq!(controller.get_state(), PyroState::DISCHARGING);
+    }
+
+    #[test]
+    fn fire_error_leaves_circuit_safe() {
+        let (mut controller, log) = controller_with_failing(Some("ignition"));
+        assert_eq!(
+            controller.fire(PyroChannelName::Ignition, 100),
+            Err(PyroError::PyroChannelError)
+        );
+        assert_eq!(
+            take(&log),
+            vec![
+                ("pyro1", false),
+                ("pyro2", false),
+                ("charge", false),
+                ("discharge", false),
+            ]
+        );
+        assert_eq!(controller.active_pulse(), None);
+    }
+
+    #[test]
+    fn fire_unknown_channel() {
+        let (mut controller, log) = controller();
+        assert_eq!(
+            controller.fire(PyroChannelName::Any, 100),
+            Err(PyroError::UnknownChannel)
+        );
+        assert!(take(&log).is_empty());
+        assert_eq!(
+            controller.add_group(PyroChannelName::Any, FireMode::Simultaneous, &[]),
+            Err(PyroError::UnknownChannel)
+        );
+    }
+
+    #[test]
+    fn fire_simultaneous_group() {
+        let (mut controller, log) = controller();
+        controller
+            .add_group(
+                PyroChannelName::Pyro1,
+                FireMode::Simultaneous,
+                &[PyroChannelName::Pyro1, PyroChannelName::Pyro2],
+            )
+            .unwrap();
+
+        let pulse = controller.fire(PyroChannelName::Pyro1, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro1", true), ("pyro2", true)]);
+
+        assert_eq!(controller.end_pulse(pulse), Ok(true));
+        assert_eq!(take(&log), vec![("pyro1", false), ("pyro2", false)]);
+    }
+
+    #[test]
+    fn fire_any_with_continuity() {
+        let (mut controller, log) = controller();
+        controller
+            .add_group(
+                PyroChannelName::Any,
+                FireMode::AnyWithContinuity,
+                &[PyroChannelName::Pyro1, PyroChannelName::Pyro2],
+            )
+            .unwrap();
+
+        assert_eq!(
+            controller.fire(PyroChannelName::Any, 100),
+            Err(PyroError::NoChannelAvailable)
+        );
+        // Discharge is active low
+        assert_eq!(
+            take(&log),
+            expected(&[("charge", false), ("discharge", false)])
+        );
+        assert_eq!(controller.get_state(), PyroState::DISCHARGING);
+
+        controller.update_continuity(PyroChannelName::Pyro1, 4095);
+        controller.update_continuity(PyroChannelName::Pyro2, 2000);
+        controller.update_continuity(PyroChannelName::Ignition, 2000);
+        controller.fire(PyroChannelName::Any, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro2", true)]);
+    }
+
+    #[test]
+    fn fire_primary_with_fallback() {
+        let (mut controller, log) = controller_with_failing(Some("ignition"));
+        controller
+            .add_group(
+                PyroChannelName::Ignition,
+                FireMode::PrimaryWithFallback,
+                &[PyroChannelName::Ignition, PyroChannelName::Pyro2],
+            )
+            .unwrap();
+        controller
+            .add_group(
+                PyroChannelName::Pyro1,
+                FireMode::PrimaryWithFallback,
+                &[PyroChannelName::Pyro1, PyroChannelName::Pyro2],
+            )
+            .unwrap();
+
+        for channel in [
+            PyroChannelName::Pyro1,
+            PyroChannelName::Ignition,
+            PyroChannelName::Pyro2,
+        ] {
+            controller.update_continuity(channel, 2000);
         }
+
+        // Primary pin fails
+        let pulse = controller.fire(PyroChannelName::Ignition, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro2", true)]);
+        controller.end_pulse(pulse).unwrap();
+        take(&log);
+
+        // Primary is open
+        controller.update_continuity(PyroChannelName::Pyro1, 4095);
+        let pulse = controller.fire(PyroChannelName::Pyro1, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro2", true)]);
+
+        // Backup is shorted as well
+        controller.end_pulse(pulse).unwrap();
+        controller.update_continuity(PyroChannelName::Pyro2, 0);
+        take(&log);
+        assert_eq!(
+            controller.fire(PyroChannelName::Pyro1, 100),
+            Err(PyroError::NoChannelAvailable)
+        );
+        assert_eq!(controller.get_state(), PyroState::DISCHARGING);
+    }
+
+    #[test]
+    fn fallback_skips_unmeasured_channels() {
+        let (mut controller, log) = controller();
+        controller
+            .add_group(
+                PyroChannelName::Pyro1,
+                FireMode::PrimaryWithFallback,
+                &[PyroChannelName::Pyro1, PyroChannelName::Pyro2],
+            )
+            .unwrap();
+
+        assert_eq!(
+            controller.fire(PyroChannelName::Pyro1, 100),
+            Err(PyroError::NoChannelAvailable)
+        );
+        take(&log);
+
+        controller.update_continuity(PyroChannelName::Pyro2, 2000);
+        controller.fire(PyroChannelName::Pyro1, 100).unwrap();
+        assert_eq!(take(&log), vec![("pyro2", true)]);
     }
 }
@@ -0,0 +1,167 @@
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+
+use crate::{
+    aero::{message, service_code},
+    pyro::{PyroChannelName, PyroError, PyroState},
+    record_log::{Record, RecordLog},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PyroAction {
+    State(PyroState),
+    ChannelOn(PyroChannelName),
+    ChannelOff(PyroChannelName),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionOutcome {
+    Done,
+    /// The transition is not allowed from the state the controller was in
+    Rejected,
+    /// A pin could not be driven, the controller has been safed
+    Failed,
+}
+
+impl<T> From<&Result<T, PyroError>> for ActionOutcome {
+    fn from(result: &Result<T, PyroError>) -> Self {
+        match result {
+            Ok(_) => ActionOutcome::Done,
+            Err(PyroError::StateChangeError(_)) => ActionOutcome::Rejected,
+            Err(_) => ActionOutcome::Failed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PyroLogEntry {
+    /// Milliseconds since boot
+    pub timestamp_ms: u32,
+    pub action: PyroAction,
+    pub outcome: ActionOutcome,
+}
+
+impl PyroLogEntry {
+    /// Encodes the entry as a timestamp message followed by an action message, both carrying
+    /// `index` as message code.
+    pub fn to_messages(&self, id: u16, index: u8) -> [CANAerospaceMessage; 2] {
+        let (kind, arg, channel) = match self.action {
+            PyroAction::State(state) => match state {
+                PyroState::FIRING(channel) => (0, state_code(state), channel_code(channel)),
+                _ => (0, state_code(state), 0),
+            },
+            PyroAction::ChannelOn(channel) => (1, channel_code(channel), 0),
+            PyroAction::ChannelOff(channel) => (2, channel_code(channel), 0),
+        };
+        let outcome = match self.outcome {
+            ActionOutcome::Done => 0,
+            ActionOutcome::Rejected => 1,
+            ActionOutcome::Failed => 2,
+        };
+        [
+            message(id, index, DataType::ULONG(self.timestamp_ms)),
+            message(id, index, DataType::UCHAR4([kind, arg, channel, outcome])),
+        ]
+    }
+}
+
+pub fn state_code(state: PyroState) -> u8 {
+    match state {
+        PyroState::IDLE => 0,
+        PyroState::CHARGING => 1,
+        PyroState::DISCHARGING => 2,
+        PyroState::READY => 3,
+        PyroState::FIRING(_) => 4,
+    }
+}
+
+pub fn channel_code(channel: PyroChannelName) -> u8 {
+    match channel {
+        PyroChannelName::Pyro1 => 1,
+        PyroChannelName::Pyro2 => 2,
+        PyroChannelName::Ignition => 3,
+        PyroChannelName::Any => 0xFF,
+    }
+}
+
+impl Record for PyroLogEntry {
+    const MESSAGES: usize = 2;
+    const SERVICE_CODE: u8 = service_code::PYRO_LOG;
+
+    fn message(&self, id: u16, index: u8, part: usize) -> CANAerospaceMessage {
+        self.to_messages(id, index)[part]
+    }
+}
+
+/// Ring buffer of the latest `N` pyro actions, older entries are overwritten.
+pub type PyroLog<const N: usize> = RecordLog<PyroLogEntry, N>;
+
+impl<const N: usize> PyroLog<N> {
+    pub fn record(&mut self, timestamp_ms: u32, action: PyroAction, outcome: ActionOutcome) {
+        self.push(PyroLogEntry {
+            timestamp_ms,
+            action,
+            outcome,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_entries_in_order() {
+        let mut log = PyroLog::<2>::new();
+        assert!(log.is_empty());
+        log.record(
+            10,
+            PyroAction::State(PyroState::CHARGING),
+            ActionOutcome::Done,
+        );
+        log.record(20, PyroAction::State(PyroState::READY), ActionOutcome::Done);
+        log.record(
+            30,
+            PyroAction::ChannelOn(PyroChannelName::Ignition),
+            ActionOutcome::Failed,
+        );
+
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.get(0).map(|entry| entry.timestamp_ms), Some(20));
+        assert_eq!(log.get(1).map(|entry| entry.timestamp_ms), Some(30));
+        assert_eq!(log.get(2), None);
+    }
+
+    #[test]
+    fn encodes_entry() {
+        let entry = PyroLogEntry {
+            timestamp_ms: 0x0102_0304,
+            action: PyroAction::State(PyroState::FIRING(PyroChannelName::Pyro2)),
+            outcome: ActionOutcome::Rejected,
+        };
+        let [timestamp, action] = entry.to_messages(2001, 7);
+        assert_eq!(timestamp.header.message_code, 7);
+        assert_eq!(timestamp.data, DataType::ULONG(0x0102_0304));
+        assert_eq!(action.data, DataType::UCHAR4([0, 4, 2, 1]));
+    }
+
+    #[test]
+    fn dump() {
+        let mut log = PyroLog::<4>::new();
+        log.record(
+            5,
+            PyroAction::ChannelOff(PyroChannelName::Pyro1),
+            ActionOutcome::Done,
+        );
+
+        let messages: std::vec::Vec<_> = (0..)
+            .map_while(|index| log.dump_message(2001, index))
+            .collect();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].data, DataType::UCHAR4([2, 1, 0, 0]));
+        assert_eq!(messages[2].data, DataType::NODATA);
+        assert_eq!(messages[2].header.message_code, 1);
+        assert!(messages
+            .iter()
+            .all(|message| message.header.service_code == service_code::PYRO_LOG));
+    }
+}
@@ -0,0 +1,75 @@
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+use heapless::HistoryBuffer;
+
+use crate::aero::message;
+
+/// Entry of a [`RecordLog`], dumped as `MESSAGES` messages carrying the entry index as message
+/// code.
+pub trait Record: Copy {
+    const MESSAGES: usize;
+    /// Service code of the dump request, also set on the dump messages
+    const SERVICE_CODE: u8;
+
+    /// Returns message `part` of the entry encoding, `part` is less than `MESSAGES`.
+    fn message(&self, id: u16, index: u8, part: usize) -> CANAerospaceMessage;
+}
+
+/// Ring buffer of the latest `N` records, older entries are overwritten.
+pub struct RecordLog<R, const N: usize> {
+    entries: HistoryBuffer<R, N>,
+}
+
+impl<R: Record, const N: usize> RecordLog<R, N> {
+    pub fn new() -> Self {
+        Self {
+            entries: HistoryBuffer::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: R) {
+        self.entries.write(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+
+    /// Returns the entry at `index`, counted from the oldest one.
+    pub fn get(&self, index: usize) -> Option<R> {
+        self.entries.oldest_ordered().nth(index).copied()
+    }
+
+    /// Returns message `index` of a dump: [`Record::MESSAGES`] messages per entry from the oldest
+    /// to the latest, followed by an end message carrying the number of entries.
+    ///
+    /// Returns `None` past the end of the dump.
+    pub fn dump_message(&self, id: u16, index: usize) -> Option<CANAerospaceMessage> {
+        let entries = self.len();
+        let mut dump = if index < R::MESSAGES * entries {
+            let entry = index / R::MESSAGES;
+            self.get(entry)?
+                .message(id, entry as u8, index % R::MESSAGES)
+        } else if index == R::MESSAGES * entries {
+            message(id, entries as u8, DataType::NODATA)
+        } else {
+            return None;
+        };
+        dump.header.service_code = R::SERVICE_CODE;
+        Some(dump)
+    }
+
+    /// Iterates from the oldest to the latest entry.
+    pub fn iter(&self) -> impl Iterator<Item = &R> {
+        self.entries.oldest_ordered()
+    }
+}
+
+impl<R: Record, const N: usize> Default for RecordLog<R, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
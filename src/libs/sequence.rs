@@ -0,0 +1,387 @@
+//! Pyro transition of a system state change, run by the state handler.
+
+use crate::{
+    engine::{Conditions, PyroStep, Trigger},
+    event::StateEvent,
+    pyro::PyroState,
+    state::{PathError, StateTransition},
+    StateEnum,
+};
+
+/// Guards waiting on time or sensors are re-evaluated at this period, a step that could not be
+/// handed to the pyro handler is retried at the same period.
+pub const GUARD_POLL_MS: u32 = 100;
+
+/// What a [`PyroSequence`] needs from its surroundings: the firmware tasks or the simulator.
+pub trait SequenceRuntime {
+    fn now_ms(&self) -> u32;
+    /// Hands the step over to the pyro handler, `false` if it could not be handed over.
+    fn spawn_step(&mut self, step: PyroState, trigger: Option<Trigger>) -> bool;
+    /// Starts the step watchdog over, it reports the transition as stuck once it expires.
+    fn arm_watchdog(&mut self);
+    fn cancel_watchdog(&mut self);
+    /// Calls the state handler again without an event after `after_ms`.
+    fn schedule_poll(&mut self, after_ms: u32);
+    fn cancel_poll(&mut self);
+}
+
+/// Outcome of advancing a [`PyroSequence`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceUpdate {
+    /// The sequence waits for its current step or for the guard of the next one
+    Pending,
+    /// The last step has completed, the target state has to be committed
+    Complete(StateEnum, Trigger),
+    /// The pyro controller has refused a step, the sequence has been given up
+    Rejected(StateEnum, Trigger),
+    /// The sequence of an already committed state has completed or has been given up on a refused
+    /// step, there is nothing left to commit
+    Finished,
+}
+
+/// Pyro transition of a system state change. It is owned by the state handler, the pyro handler
+/// only ever receives a copy of the step it has to execute.
+pub struct PyroSequence {
+    transition: StateTransition<PyroState, 5>,
+    /// Committed once the last step has completed, along with the trigger of the transition
+    target: Option<(StateEnum, Trigger)>,
+    /// The target has been committed before the sequence was started
+    committed: bool,
+    step_spawned: bool,
+}
+
+impl PyroSequence {
+    pub fn new() -> Self {
+        Self {
+            transition: StateTransition::new(),
+            target: None,
+            committed: false,
+            step_spawned: false,
+        }
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Whether the sequence runs for a target that has already been committed, see
+    /// [`PyroSequence::start_committed`].
+    pub fn committed(&self) -> bool {
+        self.committed
+    }
+
+    /// Starts the sequence along `path`, `target` is committed once the last step has completed.
+    /// The first step is entered right away, paths with a guard on it are rejected, see
+    /// [`crate::state::Step`].
+    pub fn start(
+        &mut self,
+        runtime: &mut impl SequenceRuntime,
+        path: &[PyroStep],
+        target: StateEnum,
+        trigger: Trigger,
+    ) -> Result<(), PathError<PyroState>> {
+        self.begin(runtime, path, target, trigger, false)
+    }
+
+    /// Starts the sequence along `path` for a `target` that has already been committed, the
+    /// sequence ends with [`SequenceUpdate::Finished`] whether it completes or a step is refused.
+    pub fn start_committed(
+        &mut self,
+        runtime: &mut impl SequenceRuntime,
+        path: &[PyroStep],
+        target: StateEnum,
+        trigger: Trigger,
+    ) -> Result<(), PathError<PyroState>> {
+        self.begin(runtime, path, target, trigger, true)
+    }
+
+    /// Advances the sequence on `event`, or re-evaluates the guard of the waiting step without
+    /// one.
+    ///
+    /// A transition that cannot be advanced is given up and its error returned, it has to be
+    /// reported as stuck.
+    pub fn update(
+        &mut self,
+        runtime: &mut impl SequenceRuntime,
+        event: Option<StateEvent>,
+        conditions: &Conditions,
+    ) -> Result<SequenceUpdate, PathError<PyroState>> {
+        let (step, (target, trigger)) = match (self.transition.state(), self.target) {
+            (Some(step), Some(target)) => (step, target),
+            _ => return Ok(SequenceUpdate::Pending),
+        };
+        let now = runtime.now_ms();
+        if self.step_spawned {
+            if event == Some(StateEvent::StepRejected(step)) {
+                let committed = self.committed;
+                self.cancel(runtime);
+                return Ok(if committed {
+                    SequenceUpdate::Finished
+                } else {
+                    SequenceUpdate::Rejected(target, trigger)
+                });
+            }
+            if event != Some(StateEvent::Pyro(step)) {
+                return Ok(SequenceUpdate::Pending);
+            }
+            self.step_spawned = false;
+            match self.transition.next(now) {
+                Ok(true) => runtime.arm_watchdog(),
+                Ok(false) => {
+                    let committed = self.committed;
+                    self.cancel(runtime);
+                    return Ok(if committed {
+                        SequenceUpdate::Finished
+                    } else {
+                        SequenceUpdate::Complete(target, trigger)
+                    });
+                }
+                Err(e) => {
+                    self.cancel(runtime);
+                    return Err(e);
+                }
+            }
+        }
+        // The completion of the previous step counts for the guard of the next one
+        if let Some(event) = event {
+            self.transition.record(event);
+        }
+
+        runtime.cancel_poll();
+        if self.transition.guard_passes(now, conditions) {
+            self.spawn_step(runtime, event.map(Trigger::Event));
+        } else if self
+            .transition
+            .guard()
+            .map_or(false, |guard| guard.is_polled())
+        {
+            runtime.schedule_poll(GUARD_POLL_MS);
+        }
+        Ok(SequenceUpdate::Pending)
+    }
+
+    pub fn cancel(&mut self, runtime: &mut impl SequenceRuntime) {
+        self.transition.reset();
+        self.target = None;
+        self.committed = false;
+        self.step_spawned = false;
+        runtime.cancel_watchdog();
+        runtime.cancel_poll();
+    }
+
+    fn begin(
+        &mut self,
+        runtime: &mut impl SequenceRuntime,
+        path: &[PyroStep],
+        target: StateEnum,
+        trigger: Trigger,
+        committed: bool,
+    ) -> Result<(), PathError<PyroState>> {
+        self.cancel(runtime);
+        self.transition = StateTransition::builder().path(path).build()?;
+        self.transition.start(runtime.now_ms());
+        self.target = Some((target, trigger));
+        self.committed = committed;
+        runtime.arm_watchdog();
+        self.spawn_step(runtime, Some(trigger));
+        Ok(())
+    }
+
+    fn spawn_step(&mut self, runtime: &mut impl SequenceRuntime, trigger: Option<Trigger>) {
+        if let Some(step) = self.transition.state() {
+            self.step_spawned = runtime.spawn_step(step, trigger);
+            if !self.step_spawned {
+                // The guard has passed and still does on the next poll, until the watchdog gives up
+                runtime.schedule_poll(GUARD_POLL_MS);
+            }
+        }
+    }
+}
+
+impl Default for PyroSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{find_transition, PROPULSION_CHARGE_HOLD_MS},
+        pyro::PyroState,
+    };
+    use std::vec::Vec as StdVec;
+
+    #[derive(Default)]
+    struct MockRuntime {
+        now: u32,
+        refuse_spawns: bool,
+        spawned: StdVec<PyroState>,
+        watchdog_armed: bool,
+        poll: Option<u32>,
+    }
+
+    impl SequenceRuntime for MockRuntime {
+        fn now_ms(&self) -> u32 {
+            self.now
+        }
+
+        fn spawn_step(&mut self, step: PyroState, _trigger: Option<Trigger>) -> bool {
+            if self.refuse_spawns {
+                return false;
+            }
+            self.spawned.push(step);
+            true
+        }
+
+        fn arm_watchdog(&mut self) {
+            self.watchdog_armed = true;
+        }
+
+        fn cancel_watchdog(&mut self) {
+            self.watchdog_armed = false;
+        }
+
+        fn schedule_poll(&mut self, after_ms: u32) {
+            self.poll = Some(self.now + after_ms);
+        }
+
+        fn cancel_poll(&mut self) {
+            self.poll = None;
+        }
+    }
+
+    fn armed() -> Conditions {
+        Conditions {
+            continuity_ok: true,
+            ..Conditions::default()
+        }
+    }
+
+    fn start_arming(sequence: &mut PyroSequence, runtime: &mut MockRuntime) {
+        let transition = find_transition(
+            StateEnum::IDLE,
+            Trigger::Request(StateEnum::READY),
+            &armed(),
+        )
+        .unwrap();
+        sequence
+            .start(
+                runtime,
+                transition.pyro_path,
+                transition.target,
+                Trigger::Request(StateEnum::READY),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn runs_path_and_completes() {
+        let (mut sequence, mut runtime) = (PyroSequence::new(), MockRuntime::default());
+        start_arming(&mut sequence, &mut runtime);
+        assert!(sequence.in_progress());
+        assert!(runtime.watchdog_armed);
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+
+        // Unrelated events do not advance the step in progress
+        let update = sequence.update(&mut runtime, Some(StateEvent::Liftoff), &armed());
+        assert_eq!(update, Ok(SequenceUpdate::Pending));
+        let charging = Some(StateEvent::Pyro(PyroState::CHARGING));
+        assert_eq!(
+            sequence.update(&mut runtime, charging, &armed()),
+            Ok(SequenceUpdate::Pending)
+        );
+        // READY waits for the charge
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+        let charged = Some(StateEvent::ChargeComplete);
+        sequence.update(&mut runtime, charged, &armed()).unwrap();
+        assert_eq!(runtime.spawned, [PyroState::CHARGING, PyroState::READY]);
+
+        let ready = Some(StateEvent::Pyro(PyroState::READY));
+        assert_eq!(
+            sequence.update(&mut runtime, ready, &armed()),
+            Ok(SequenceUpdate::Complete(
+                StateEnum::READY,
+                Trigger::Request(StateEnum::READY)
+            ))
+        );
+        assert!(!sequence.in_progress());
+        assert!(!runtime.watchdog_armed);
+    }
+
+    #[test]
+    fn rejected_step_gives_up() {
+        let (mut sequence, mut runtime) = (PyroSequence::new(), MockRuntime::default());
+        start_arming(&mut sequence, &mut runtime);
+        let rejected = Some(StateEvent::StepRejected(PyroState::CHARGING));
+        assert_eq!(
+            sequence.update(&mut runtime, rejected, &armed()),
+            Ok(SequenceUpdate::Rejected(
+                StateEnum::READY,
+                Trigger::Request(StateEnum::READY)
+            ))
+        );
+        assert!(!sequence.in_progress());
+        assert!(!runtime.watchdog_armed);
+    }
+
+    #[test]
+    fn failed_spawn_is_retried() {
+        let (mut sequence, mut runtime) = (PyroSequence::new(), MockRuntime::default());
+        runtime.refuse_spawns = true;
+        start_arming(&mut sequence, &mut runtime);
+        assert!(runtime.spawned.is_empty());
+        assert_eq!(runtime.poll, Some(GUARD_POLL_MS));
+
+        runtime.refuse_spawns = false;
+        runtime.now = GUARD_POLL_MS;
+        sequence.update(&mut runtime, None, &armed()).unwrap();
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+        assert_eq!(runtime.poll, None);
+    }
+
+    #[test]
+    fn polls_time_guards() {
+        let (mut sequence, mut runtime) = (PyroSequence::new(), MockRuntime::default());
+        let transition = find_transition(
+            StateEnum::IGNITION,
+            Trigger::Event(StateEvent::Liftoff),
+            &armed(),
+        )
+        .unwrap();
+        sequence
+            .start_committed(
+                &mut runtime,
+                transition.pyro_path,
+                transition.target,
+                Trigger::Event(StateEvent::Liftoff),
+            )
+            .unwrap();
+        assert!(sequence.committed());
+        let charging = Some(StateEvent::Pyro(PyroState::CHARGING));
+        sequence.update(&mut runtime, charging, &armed()).unwrap();
+        assert_eq!(runtime.poll, Some(GUARD_POLL_MS));
+
+        runtime.now = 1000;
+        sequence
+            .update(&mut runtime, Some(StateEvent::ChargeComplete), &armed())
+            .unwrap();
+        assert_eq!(runtime.spawned, [PyroState::CHARGING]);
+        assert_eq!(runtime.poll, Some(1000 + GUARD_POLL_MS));
+
+        runtime.now = PROPULSION_CHARGE_HOLD_MS;
+        sequence.update(&mut runtime, None, &armed()).unwrap();
+        assert_eq!(runtime.spawned, [PyroState::CHARGING, PyroState::READY]);
+        assert_eq!(runtime.poll, None);
+
+        // The target has been committed already
+        let ready = Some(StateEvent::Pyro(PyroState::READY));
+        assert_eq!(
+            sequence.update(&mut runtime, ready, &armed()),
+            Ok(SequenceUpdate::Finished)
+        );
+        assert!(!sequence.in_progress());
+        assert!(!sequence.committed());
+    }
+}
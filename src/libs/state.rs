@@ -2,60 +2,267 @@ use heapless::Vec;
 
 use crate::event::StateEvent;
 
+/// Events remembered while a step waits for its guard.
+const SEEN_EVENTS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathError<S> {
+    /// The path holds more steps than the transition has room for
+    Capacity,
+    /// The step from the first state to the second one is not allowed
+    IllegalStep(S, S),
+    /// The transition has to be started before it can be advanced
+    NotStarted,
+    /// The first step is entered as soon as the transition starts, it cannot have a guard
+    GuardedFirstStep,
+}
+
+/// Condition a step has to meet before its state is entered.
+pub enum StepGuard<C: 'static> {
+    /// Entered right away
+    Always,
+    /// The event has arrived since the previous step was entered
+    Event(StateEvent),
+    /// At least this many milliseconds have passed since the previous step was entered
+    Elapsed(u32),
+    /// Sensor predicate, evaluated against the context of the transition
+    Check(fn(&C) -> bool),
+    All(&'static [StepGuard<C>]),
+    Any(&'static [StepGuard<C>]),
+}
+
+impl<C> StepGuard<C> {
+    pub fn passes(&self, seen: &[StateEvent], elapsed_ms: u32, context: &C) -> bool {
+        match self {
+            StepGuard::Always => true,
+            StepGuard::Event(event) => seen.contains(event),
+            StepGuard::Elapsed(ms) => elapsed_ms >= *ms,
+            StepGuard::Check(predicate) => predicate(context),
+            StepGuard::All(guards) => guards
+                .iter()
+                .all(|guard| guard.passes(seen, elapsed_ms, context)),
+            StepGuard::Any(guards) => guards
+                .iter()
+                .any(|guard| guard.passes(seen, elapsed_ms, context)),
+        }
+    }
+
+    /// Whether the guard can pass without another event, it then has to be re-evaluated
+    /// periodically.
+    pub fn is_polled(&self) -> bool {
+        match self {
+            StepGuard::Always | StepGuard::Event(_) => false,
+            StepGuard::Elapsed(_) | StepGuard::Check(_) => true,
+            StepGuard::All(guards) | StepGuard::Any(guards) => {
+                guards.iter().any(|guard| guard.is_polled())
+            }
+        }
+    }
+}
+
+impl<C> Clone for StepGuard<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for StepGuard<C> {}
+
+/// A step of a transition path, without a guard of its own the state's default guard is used.
+///
+/// Guards are evaluated from the second step on. The first step is entered when the transition
+/// starts, whatever released the transition is its guard.
+pub struct Step<S, C: 'static> {
+    pub state: S,
+    pub guard: Option<StepGuard<C>>,
+}
+
+impl<S, C> Step<S, C> {
+    pub const fn new(state: S) -> Self {
+        Self { state, guard: None }
+    }
+
+    pub const fn guarded(state: S, guard: StepGuard<C>) -> Self {
+        Self {
+            state,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<S: Copy, C> Clone for Step<S, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: Copy, C> Copy for Step<S, C> {}
+
+/// Collects the steps of a transition and validates the whole path before it can be run.
+pub struct TransitionBuilder<S: MovingState + Copy, const N: usize> {
+    path: Vec<Step<S, S::Context>, N>,
+    error: Option<PathError<S>>,
+}
+
+impl<S: MovingState + Copy, const N: usize> TransitionBuilder<S, N> {
+    pub fn new() -> Self {
+        Self {
+            path: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn step(self, state: S) -> Self {
+        self.add(Step::new(state))
+    }
+
+    pub fn guarded_step(self, state: S, guard: StepGuard<S::Context>) -> Self {
+        self.add(Step::guarded(state, guard))
+    }
+
+    pub fn steps(self, states: &[S]) -> Self {
+        states
+            .iter()
+            .fold(self, |builder, state| builder.step(*state))
+    }
+
+    pub fn path(self, steps: &[Step<S, S::Context>]) -> Self {
+        steps.iter().fold(self, |builder, step| builder.add(*step))
+    }
+
+    /// Returns the transition, or the first error found in its path.
+    pub fn build(self) -> Result<StateTransition<S, N>, PathError<S>> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(StateTransition {
+                current_index: None,
+                transition_path: self.path,
+                entered_at: 0,
+                seen: Vec::new(),
+            }),
+        }
+    }
+
+    fn add(mut self, step: Step<S, S::Context>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        match self.path.last() {
+            Some(previous) if !previous.state.is_transition_allowed(step.state) => {
+                self.error = Some(PathError::IllegalStep(previous.state, step.state));
+                return self;
+            }
+            None if step.guard.is_some() => {
+                self.error = Some(PathError::GuardedFirstStep);
+                return self;
+            }
+            _ => {}
+        }
+        if self.path.push(step).is_err() {
+            self.error = Some(PathError::Capacity);
+        }
+        self
+    }
+}
+
+impl<S: MovingState + Copy, const N: usize> Default for TransitionBuilder<S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A validated path of states, built with [`TransitionBuilder`].
 pub struct StateTransition<S: MovingState + Copy, const N: usize> {
-    pub current_index: usize,
-    pub transition_path: Vec<S, N>,
+    /// `None` until the transition is started
+    current_index: Option<usize>,
+    transition_path: Vec<Step<S, S::Context>, N>,
+    /// When the previous step was entered, the start of the transition for the first step
+    entered_at: u32,
+    /// Events that arrived while waiting for the current step
+    seen: Vec<StateEvent, SEEN_EVENTS>,
 }
 
 impl<S: MovingState + Copy, const N: usize> StateTransition<S, N> {
+    /// An empty transition, it is finished as soon as it is started.
     pub fn new() -> Self {
         Self {
-            current_index: 0xFF,
+            current_index: None,
             transition_path: Vec::new(),
+            entered_at: 0,
+            seen: Vec::new(),
         }
     }
 
-    pub fn add_state(&mut self, state: S) {
-        self.transition_path.push(state).ok().unwrap();
+    pub fn builder() -> TransitionBuilder<S, N> {
+        TransitionBuilder::new()
     }
 
-    pub fn next(&mut self) -> bool {
-        if !self.finished() {
-            if self.current_index + 1 >= self.transition_path.len() {
-                self.current_index += 1;
-                return false;
-            }
-
-            let curr_state: S = self.transition_path[self.current_index];
-            let next_state: S = self.transition_path[self.current_index + 1];
-            if curr_state.is_transition_allowed(next_state) {
-                self.current_index += 1;
-                return true;
-            } else {
-                return false;
-            }
+    /// Advances to the next step, returns `false` once the path is complete.
+    pub fn next(&mut self, now_ms: u32) -> Result<bool, PathError<S>> {
+        let index = self.current_index.ok_or(PathError::NotStarted)?;
+        if index < self.transition_path.len() {
+            self.current_index = Some(index + 1);
         }
-        false
+        self.entered_at = now_ms;
+        self.seen.clear();
+        Ok(!self.finished())
+    }
+
+    pub fn started(&self) -> bool {
+        self.current_index.is_some()
     }
 
     pub fn finished(&self) -> bool {
-        self.current_index >= self.transition_path.len()
+        match self.current_index {
+            Some(index) => index >= self.transition_path.len(),
+            None => false,
+        }
     }
 
-    pub fn start(&mut self) {
-        self.current_index = 0;
+    pub fn start(&mut self, now_ms: u32) {
+        self.current_index = Some(0);
+        self.entered_at = now_ms;
+        self.seen.clear();
     }
 
     pub fn reset(&mut self) {
-        self.current_index = 0xFF;
+        self.current_index = None;
         self.transition_path.clear();
+        self.seen.clear();
     }
 
+    /// The current step, `None` before the start and once the path is complete.
     pub fn state(&self) -> Option<S> {
-        if !self.finished() {
-            return Some(self.transition_path[self.current_index]);
+        self.step().map(|step| step.state)
+    }
+
+    /// The guard of the current step, the first step always passes, see [`Step`].
+    pub fn guard(&self) -> Option<StepGuard<S::Context>> {
+        let step = self.step()?;
+        Some(match step.guard {
+            Some(guard) => guard,
+            None if self.current_index == Some(0) => StepGuard::Always,
+            None => step.state.guard(),
+        })
+    }
+
+    /// Remembers `event` for the guard of the current step.
+    pub fn record(&mut self, event: StateEvent) {
+        if !self.seen.contains(&event) {
+            self.seen.push(event).ok();
+        }
+    }
+
+    /// Whether the guard of the current step passes, `false` without a current step.
+    pub fn guard_passes(&self, now_ms: u32, context: &S::Context) -> bool {
+        match self.guard() {
+            Some(guard) => guard.passes(&self.seen, now_ms.wrapping_sub(self.entered_at), context),
+            None => false,
         }
-        None
+    }
+
+    fn step(&self) -> Option<&Step<S, S::Context>> {
+        self.transition_path.get(self.current_index?)
     }
 }
 
@@ -65,6 +272,104 @@ impl<S: MovingState + Copy, const N: usize> Default for StateTransition<S, N> {
     }
 }
 pub trait MovingState {
-    fn get_required_events(&self) -> Vec<StateEvent, 5>;
+    /// What the sensor predicates of the step guards are evaluated against
+    type Context: 'static;
+
+    /// Guard of the state when the transition path does not give one.
+    fn guard(&self) -> StepGuard<Self::Context>;
     fn is_transition_allowed(&self, state: Self) -> bool;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Conditions, pyro::PyroState};
+
+    #[test]
+    fn builds_and_runs_valid_path() {
+        let mut transition: StateTransition<PyroState, 2> = StateTransition::builder()
+            .steps(&[PyroState::CHARGING, PyroState::READY])
+            .build()
+            .unwrap();
+        assert_eq!(transition.next(0), Err(PathError::NotStarted));
+        assert_eq!(transition.state(), None);
+
+        transition.start(0);
+        assert_eq!(transition.state(), Some(PyroState::CHARGING));
+        assert_eq!(transition.next(0), Ok(true));
+        assert_eq!(transition.state(), Some(PyroState::READY));
+        assert_eq!(transition.next(0), Ok(false));
+        assert!(transition.finished());
+        assert_eq!(transition.state(), None);
+        assert_eq!(transition.next(0), Ok(false));
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        let illegal = StateTransition::<PyroState, 5>::builder()
+            .steps(&[PyroState::CHARGING, PyroState::IDLE, PyroState::DISCHARGING])
+            .build();
+        assert_eq!(
+            illegal.err(),
+            Some(PathError::IllegalStep(PyroState::CHARGING, PyroState::IDLE))
+        );
+
+        let too_long = StateTransition::<PyroState, 1>::builder()
+            .steps(&[PyroState::CHARGING, PyroState::READY])
+            .build();
+        assert_eq!(too_long.err(), Some(PathError::Capacity));
+
+        let guarded_first = StateTransition::<PyroState, 1>::builder()
+            .guarded_step(PyroState::CHARGING, StepGuard::Elapsed(1000))
+            .build();
+        assert_eq!(guarded_first.err(), Some(PathError::GuardedFirstStep));
+    }
+
+    #[test]
+    fn evaluates_step_guards() {
+        const HOLD: StepGuard<Conditions> = StepGuard::All(&[
+            StepGuard::Event(StateEvent::ChargeComplete),
+            StepGuard::Elapsed(3000),
+        ]);
+        let armed = Conditions {
+            continuity_ok: true,
+            ..Conditions::default()
+        };
+        let mut transition: StateTransition<PyroState, 3> = StateTransition::builder()
+            .step(PyroState::CHARGING)
+            .guarded_step(PyroState::READY, HOLD)
+            .guarded_step(
+                PyroState::FIRING(crate::pyro::PyroChannelName::Ignition),
+                StepGuard::Check(|c: &Conditions| c.continuity_ok),
+            )
+            .build()
+            .unwrap();
+        transition.start(0);
+        assert!(transition.guard_passes(0, &armed));
+        transition.next(1000).unwrap();
+
+        // Both the event and the hold time are required, in any order
+        assert!(transition.guard().unwrap().is_polled());
+        transition.record(StateEvent::ChargeComplete);
+        assert!(!transition.guard_passes(3999, &armed));
+        assert!(transition.guard_passes(4000, &armed));
+
+        transition.next(4000).unwrap();
+        assert!(!transition.guard_passes(4000, &Conditions::default()));
+        assert!(transition.guard_passes(4000, &armed));
+    }
+
+    #[test]
+    fn default_guards() {
+        let mut transition: StateTransition<PyroState, 2> = StateTransition::builder()
+            .steps(&[PyroState::CHARGING, PyroState::READY])
+            .build()
+            .unwrap();
+        transition.start(0);
+        assert!(transition.guard_passes(0, &Conditions::default()));
+        transition.next(0).unwrap();
+        assert!(!transition.guard_passes(10_000, &Conditions::default()));
+        transition.record(StateEvent::ChargeComplete);
+        assert!(transition.guard_passes(0, &Conditions::default()));
+    }
+}
@@ -0,0 +1,307 @@
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+use heapless::Vec;
+
+use crate::{
+    aero::{
+        fault_flag, message, status_flag, CAN_COUNTERS_ID, CAN_ERRORS_ID, CONTINUITY_ID,
+        NODE_STATUS_ID, UPTIME_ID,
+    },
+    can_health::CanHealth,
+    continuity::Continuity,
+    engine::{AbortReason, Conditions},
+    event::Fault,
+    pyro::PyroState,
+    pyro_log::state_code,
+    StateEnum,
+};
+
+/// Publication periods of the telemetry messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TelemetryConfig {
+    pub status_period_ms: u32,
+    /// Also the period of the CAN link health
+    pub uptime_period_ms: u32,
+}
+
+/// Node status, published as `[state, pyro state, flags, faults]`, and the continuity of the pyro
+/// channels, published as one [`Continuity::code`] per channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeStatus {
+    pub state: StateEnum,
+    pub pyro_state: PyroState,
+    pub conditions: Conditions,
+    /// In the order of the board channels, entries without a channel are `Unknown`
+    pub continuity: [Continuity; 4],
+}
+
+impl NodeStatus {
+    pub fn to_data(&self) -> [u8; 4] {
+        let mut flags = self.conditions.status_flags();
+        if matches!(
+            self.state,
+            StateEnum::READY | StateEnum::IGNITION | StateEnum::PROPULSION
+        ) {
+            flags |= status_flag::ARMED;
+        }
+        if matches!(self.pyro_state, PyroState::READY | PyroState::FIRING(_)) {
+            flags |= status_flag::CHARGED;
+        }
+        let faults = match self.conditions.abort_reason {
+            None => 0,
+            Some(AbortReason::Command) => fault_flag::COMMAND,
+            Some(AbortReason::Fault(Fault::ChargeFailure)) => fault_flag::CHARGE_FAILURE,
+            Some(AbortReason::Fault(Fault::Pyro(_))) => fault_flag::PYRO,
+            Some(AbortReason::Fault(Fault::TransitionStuck)) => fault_flag::TRANSITION_STUCK,
+        };
+        [self.state as u8, state_code(self.pyro_state), flags, faults]
+    }
+
+    pub fn continuity_data(&self) -> [u8; 4] {
+        let mut data = [0; 4];
+        for (code, continuity) in data.iter_mut().zip(self.continuity.iter()) {
+            *code = continuity.code();
+        }
+        data
+    }
+}
+
+/// Schedules the telemetry messages. Every message ID counts its own message codes, as CANaerospace
+/// normal operation data does.
+pub struct Telemetry {
+    config: TelemetryConfig,
+    /// Last published status and continuity, a change is published out of cycle
+    last_status: Option<[u8; 4]>,
+    last_continuity: Option<[u8; 4]>,
+    status_due: u32,
+    continuity_due: u32,
+    uptime_due: u32,
+    status_code: u8,
+    continuity_code: u8,
+    uptime_code: u8,
+    can_counters_code: u8,
+    can_errors_code: u8,
+}
+
+impl Telemetry {
+    pub fn new(config: TelemetryConfig) -> Self {
+        Self {
+            config,
+            last_status: None,
+            last_continuity: None,
+            status_due: 0,
+            continuity_due: 0,
+            uptime_due: 0,
+            status_code: 0,
+            continuity_code: 0,
+            uptime_code: 0,
+            can_counters_code: 0,
+            can_errors_code: 0,
+        }
+    }
+
+    pub fn config(&self) -> TelemetryConfig {
+        self.config
+    }
+
+    /// Returns the messages due at `now_ms`. The status and the continuity are also due as soon as
+    /// they differ from the last published ones, their period then restarts. The CAN link health
+    /// is published along with the uptime.
+    pub fn update(
+        &mut self,
+        now_ms: u32,
+        status: &NodeStatus,
+        health: &CanHealth,
+    ) -> Vec<CANAerospaceMessage, 5> {
+        let mut messages = Vec::new();
+        let data = status.to_data();
+        if reached(now_ms, self.status_due) || self.last_status != Some(data) {
+            let status = message(NODE_STATUS_ID, self.status_code, DataType::UCHAR4(data));
+            messages.push(status).ok();
+            self.last_status = Some(data);
+            self.status_due = now_ms.wrapping_add(self.config.status_period_ms);
+            self.status_code = self.status_code.wrapping_add(1);
+        }
+        let continuity = status.continuity_data();
+        if reached(now_ms, self.continuity_due) || self.last_continuity != Some(continuity) {
+            let continuity_message = message(
+                CONTINUITY_ID,
+                self.continuity_code,
+                DataType::UCHAR4(continuity),
+            );
+            messages.push(continuity_message).ok();
+            self.last_continuity = Some(continuity);
+            self.continuity_due = now_ms.wrapping_add(self.config.status_period_ms);
+            self.continuity_code = self.continuity_code.wrapping_add(1);
+        }
+        if reached(now_ms, self.uptime_due) {
+            let uptime = message(UPTIME_ID, self.uptime_code, DataType::ULONG(now_ms));
+            messages.push(uptime).ok();
+            let counters = message(
+                CAN_COUNTERS_ID,
+                self.can_counters_code,
+                DataType::UCHAR4(health.counter_data()),
+            );
+            messages.push(counters).ok();
+            let errors = message(
+                CAN_ERRORS_ID,
+                self.can_errors_code,
+                DataType::UCHAR4(health.error_data()),
+            );
+            messages.push(errors).ok();
+            self.uptime_due = now_ms.wrapping_add(self.config.uptime_period_ms);
+            self.uptime_code = self.uptime_code.wrapping_add(1);
+            self.can_counters_code = self.can_counters_code.wrapping_add(1);
+            self.can_errors_code = self.can_errors_code.wrapping_add(1);
+        }
+        messages
+    }
+
+    /// Time from `now_ms` until the next message is due.
+    pub fn next_due_ms(&self, now_ms: u32) -> u32 {
+        let until = |due: u32| {
+            if reached(now_ms, due) {
+                0
+            } else {
+                due.wrapping_sub(now_ms)
+            }
+        };
+        until(self.status_due)
+            .min(until(self.continuity_due))
+            .min(until(self.uptime_due))
+    }
+}
+
+/// Whether `due` has been reached at `now_ms`, robust to the wrap-around of the uptime.
+fn reached(now_ms: u32, due: u32) -> bool {
+    (now_ms.wrapping_sub(due) as i32) >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aero::message_id, can_health::LastErrorCode};
+
+    const CONFIG: TelemetryConfig = TelemetryConfig {
+        status_period_ms: 100,
+        uptime_period_ms: 1000,
+    };
+
+    fn status(state: StateEnum, pyro_state: PyroState) -> NodeStatus {
+        NodeStatus {
+            state,
+            pyro_state,
+            conditions: Conditions {
+                continuity_ok: true,
+                ..Conditions::default()
+            },
+            continuity: [
+                Continuity::Ok,
+                Continuity::Ok,
+                Continuity::Ok,
+                Continuity::Unknown,
+            ],
+        }
+    }
+
+    #[test]
+    fn periodic_messages() {
+        let mut telemetry = Telemetry::new(CONFIG);
+        let idle = status(StateEnum::IDLE, PyroState::IDLE);
+
+        let messages = telemetry.update(0, &idle, &CanHealth::default());
+        assert_eq!(messages.len(), 5);
+        assert_eq!(message_id(&messages[0]), NODE_STATUS_ID);
+        assert_eq!(
+            messages[0].data,
+            DataType::UCHAR4([StateEnum::IDLE as u8, 0, status_flag::CONTINUITY_OK, 0])
+        );
+        assert_eq!(message_id(&messages[1]), CONTINUITY_ID);
+        assert_eq!(messages[1].data, DataType::UCHAR4([2, 2, 2, 0]));
+        assert_eq!(message_id(&messages[2]), UPTIME_ID);
+        assert_eq!(message_id(&messages[3]), CAN_COUNTERS_ID);
+        assert_eq!(message_id(&messages[4]), CAN_ERRORS_ID);
+        assert_eq!(telemetry.next_due_ms(0), 100);
+
+        assert!(telemetry
+            .update(99, &idle, &CanHealth::default())
+            .is_empty());
+        let messages = telemetry.update(100, &idle, &CanHealth::default());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].header.message_code, 1);
+        assert_eq!(messages[1].header.message_code, 1);
+        assert_eq!(telemetry.next_due_ms(150), 50);
+
+        for now in (200..=1000).step_by(100) {
+            telemetry.update(now, &idle, &CanHealth::default());
+        }
+        assert_eq!(telemetry.next_due_ms(1000), 100);
+    }
+
+    #[test]
+    fn state_change_is_published_out_of_cycle() {
+        let mut telemetry = Telemetry::new(CONFIG);
+        telemetry.update(
+            0,
+            &status(StateEnum::IDLE, PyroState::IDLE),
+            &CanHealth::default(),
+        );
+
+        let ready = status(StateEnum::READY, PyroState::READY);
+        let messages = telemetry.update(30, &ready, &CanHealth::default());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].data,
+            DataType::UCHAR4([
+                StateEnum::READY as u8,
+                state_code(PyroState::READY),
+                status_flag::CONTINUITY_OK | status_flag::ARMED | status_flag::CHARGED,
+                0
+            ])
+        );
+        // The period restarts with the out of cycle message, the continuity keeps its own
+        let messages = telemetry.update(100, &ready, &CanHealth::default());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(message_id(&messages[0]), CONTINUITY_ID);
+        let messages = telemetry.update(130, &ready, &CanHealth::default());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(message_id(&messages[0]), NODE_STATUS_ID);
+    }
+
+    #[test]
+    fn continuity_change_is_published_out_of_cycle() {
+        let mut telemetry = Telemetry::new(CONFIG);
+        let mut idle = status(StateEnum::IDLE, PyroState::IDLE);
+        telemetry.update(0, &idle, &CanHealth::default());
+
+        idle.continuity[1] = Continuity::Open;
+        idle.continuity[2] = Continuity::Short;
+        let messages = telemetry.update(40, &idle, &CanHealth::default());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(message_id(&messages[0]), CONTINUITY_ID);
+        assert_eq!(messages[0].data, DataType::UCHAR4([2, 1, 3, 0]));
+        assert_eq!(telemetry.next_due_ms(40), 60);
+    }
+
+    #[test]
+    fn can_health_is_published_with_uptime() {
+        let mut telemetry = Telemetry::new(CONFIG);
+        let idle = status(StateEnum::IDLE, PyroState::IDLE);
+        let mut health = CanHealth {
+            tx_errors: 3,
+            rx_overruns: 300,
+            bus_off_count: 1,
+            ..CanHealth::default()
+        };
+        health.status.tx_error_count = 136;
+        health.status.last_error = LastErrorCode::Acknowledgement;
+
+        let messages = telemetry.update(0, &idle, &health);
+        assert_eq!(messages[3].data, DataType::UCHAR4([3, 255, 0, 1]));
+        assert_eq!(messages[4].data, DataType::UCHAR4([136, 0, 3, 0]));
+
+        telemetry.update(100, &idle, &health);
+        let messages = telemetry.update(1000, &idle, &health);
+        assert_eq!(message_id(&messages[3]), CAN_COUNTERS_ID);
+        assert_eq!(messages[3].header.message_code, 1);
+    }
+}
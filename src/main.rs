@@ -1,14 +1,9 @@
 #![no_main]
 #![no_std]
-use bxcan::{filter::Mask32, Interrupts};
+use bxcan::{Interrupts, Rx0, Rx1, Tx};
 use nb::block;
-use panic_halt as _;
 
-use pike_enginecontrol::{
-    can_driver::CANDriver,
-    pin::{PINErasedPP, PINErasedPPInv},
-    pyro::{PyroChannel, PyroChannelName, PyroController},
-};
+use pike_enginecontrol::can_driver;
 use rtic::app;
 use state_governor::state::State;
 use stm32f1xx_hal::{
@@ -24,61 +19,109 @@ use stm32f1xx_hal::{
 
 const TIMER_FREQ: u32 = 1;
 
+// Bounds the time spent reporting a previous panic on a bus without any other node
+const PANIC_REPORT_ATTEMPTS: u32 = 10_000;
+
+// Telemetry publication periods, the node status is also published on every state change
+const TELEMETRY_STATUS_PERIOD_MS: u32 = 100;
+const TELEMETRY_UPTIME_PERIOD_MS: u32 = 1000;
+
+// Number of pyro actions kept for the post-test dump
+const PYRO_LOG_LEN: usize = 32;
+
+// Number of system and pyro transitions kept in the journal
+const JOURNAL_LEN: usize = 32;
+
+// Command refusals sent per receive interrupt, a receive FIFO holds three frames and more may
+// arrive while it is drained
+const MAX_REFUSALS: usize = 6;
+
+mod board;
+mod panic;
 mod tasks;
 
-#[app(device = stm32f1xx_hal::pac, peripherals = true,dispatchers = [EXTI0, EXTI1])]
+#[app(device = stm32f1xx_hal::pac, peripherals = true,dispatchers = [EXTI0, EXTI1, EXTI2])]
 mod app {
-    use can_aerospace_lite::CANAerospaceLite;
-    use core::convert::{Infallible, TryInto};
-    use heapless::{
-        mpmc::Q8,
-        spsc::{Consumer, Producer, Queue},
-    };
-    use nb::block;
+    use bxcan::{Frame, Rx1};
+    use can_aerospace_lite::{message::CANAerospaceMessage, CANAerospaceLite};
+    use heapless::Vec;
     use pike_enginecontrol::{
-        can_driver::CANDriver,
-        event::{Event, StateEvent},
-        pin::Output,
-        pyro::{PyroChannelName, PyroController, PyroState},
-        state::StateTransition,
+        aero::{self, Command, FlightData, ServiceRequest},
+        burnout::BurnoutDetector,
+        can_driver::{AeroLink, CANDriver, LinkQueue, RxFifo},
+        charge::ChargeMonitor,
+        config,
+        engine::{Conditions, Trigger},
+        event::Event,
+        liftoff::LiftoffDetector,
+        pyro::{FirePulse, PyroState},
+        pyro_log::PyroLog,
+        sequence::PyroSequence,
+        telemetry::{Telemetry, TelemetryConfig},
         StateEnum,
     };
-    use rtic::{rtic_monotonic::Instant, time::duration::*, Mutex};
+    use rtic::mutex_prelude::*;
 
     use state_governor::{state::State, Governor};
     use stm32f1xx_hal::{
-        device::TIM1,
-        gpio::{self},
-        gpio::{gpiob::PB15, gpioc::PC14, PinState, PushPull},
+        can::Can,
+        device::{CAN1, TIM1},
         i2c::{BlockingI2c, Mode},
         prelude::*,
         timer::{self, CountDownTimer, Timer},
     };
     use systick_monotonic::Systick;
 
+    use crate::{
+        board::{self, Board, LedCont, LedHeartbeat, PyroController, SensePins},
+        tasks::{state_task::SequenceTimers, TransitionJournal},
+    };
+
     #[monotonic(binds = SysTick, default = true)]
     type Mono = Systick<100>; // 100 Hz / 10 ms granularity
 
     #[local]
     struct Local {
         timer: CountDownTimer<TIM1>,
-        pyro_controller: PyroController<3>,
-        led_heartbeat: PC14<gpio::Output<PushPull>>,
-
-        led_cont: PB15<gpio::Output<PushPull>>,
+        led_heartbeat: LedHeartbeat,
+
+        led_cont: LedCont,
+        pyro_sense: SensePins,
+        charge_monitor: ChargeMonitor,
+        burnout_detector: BurnoutDetector,
+        liftoff_detector: LiftoffDetector,
+        dwell_timer: Option<state_timeout::SpawnHandle>,
+        lockout_timer: Option<lockout_end::SpawnHandle>,
+        sequence_timers: SequenceTimers,
+        telemetry: Telemetry,
+        telemetry_timer: Option<telemetry_publish::SpawnHandle>,
+        fifo1: RxFifo<Rx1<Can<CAN1>>>,
     }
 
     #[shared]
     struct Shared {
         // delay: Delay,
-        governor: Governor<5>,
-        can_aerospace: CANAerospaceLite<CANDriver>,
-        event_q: Q8<Event>,
+        governor: Governor<6>,
+        // Shared between pyro tasks of the same priority only, so it never needs a lock
+        #[lock_free]
+        pyro_controller: PyroController,
+        #[lock_free]
+        pyro_log: PyroLog<{ crate::PYRO_LOG_LEN }>,
+        // Shared between the state and command handlers, which run at the same priority
+        #[lock_free]
+        pyro_sequence: PyroSequence,
+        journal: TransitionJournal,
+        can_driver: CANDriver,
+        can_aerospace: CANAerospaceLite<AeroLink>,
+        conditions: Conditions,
         // altitude_sensor:
         //     MPL3115A2<BlockingI2c<I2C2, (PB10<Alternate<OpenDrain>>, PB11<Alternate<OpenDrain>>)>>,
     }
 
-    #[init(local = [])]
+    #[init(local = [
+        can_link_rx: LinkQueue = LinkQueue::new(),
+        can_link_tx: LinkQueue = LinkQueue::new(),
+    ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let mut governor = Governor::new();
         governor.add_state(State::from(StateEnum::IDLE));
@@ -86,6 +129,7 @@ mod app {
         governor.add_state(State::from(StateEnum::IGNITION));
         governor.add_state(State::from(StateEnum::PROPULSION));
         governor.add_state(State::from(StateEnum::BURNOUT));
+        governor.add_state(State::from(StateEnum::ABORT));
         governor.change_state_to(StateEnum::IDLE as u8);
         // Take ownership over the raw flash and rcc devices and convert them into the corresponding
         // HAL structs
@@ -108,72 +152,49 @@ mod app {
         // Initialize the monotonic
         let mono = Systick::new(cx.core.SYST, 64_000_000);
         // let delay = Delay::new(cx.core.SYST, clocks);
-        let mut gpioa = cx.device.GPIOA.split();
-        let mut gpioc = cx.device.GPIOC.split();
-        let mut gpiob = cx.device.GPIOB.split();
-        let (pa15, _, _) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
-        let can_rx_pin = gpioa.pa11.into_floating_input(&mut gpioa.crh);
-        let can_tx_pin = gpioa.pa12.into_alternate_push_pull(&mut gpioa.crh);
-
-        let can_driver = crate::initialize_canbus(
+        let board = Board::new(
+            cx.device.GPIOA.split(),
+            cx.device.GPIOB.split(),
+            cx.device.GPIOC.split(),
+            &mut afio,
+        );
+
+        let (can_tx, can_fifo0, can_fifo1) = crate::initialize_canbus(
             cx.device.CAN1,
             cx.device.USB,
-            can_rx_pin,
-            can_tx_pin,
+            board.can_rx,
+            board.can_tx,
             &mut afio,
         );
+        let (mut can_driver, can_link) = CANDriver::new(
+            can_tx,
+            can_fifo0,
+            cx.local.can_link_rx,
+            cx.local.can_link_tx,
+        );
+        let fifo1 = RxFifo::new(can_fifo1);
+        if let Some(record) = crate::panic::take_record() {
+            for message in record.messages() {
+                can_driver.transmit_blocking(&message, crate::PANIC_REPORT_ATTEMPTS);
+            }
+        }
 
         let mut timer =
             Timer::tim1(cx.device.TIM1, &clocks).start_count_down(crate::TIMER_FREQ.hz());
         timer.listen(timer::Event::Update);
 
-        // Configure gpio C pin 13 as a push-pull output. The `crh` register is passed to the
-        // function in order to configure the port. For pins 0-7, crl should be passed instead
-        // TODO: initialize peripherals in separate function
-        let led_heartbeat = gpioc
-            .pc14
-            .into_push_pull_output_with_state(&mut gpioc.crh, PinState::High);
-        let led_cont: PB15<gpio::Output<PushPull>> = gpiob
-            .pb15
-            .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low);
-
-        let pyro_controller = crate::initialize_pyro_controller(
-            Output::new(
-                gpioc
-                    .pc15
-                    .into_push_pull_output_with_state(&mut gpioc.crh, PinState::High)
-                    .erase(),
-            ),
-            Output::new(
-                gpiob
-                    .pb14
-                    .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low)
-                    .erase(),
-            ),
-            Output::new(
-                gpiob
-                    .pb13
-                    .into_push_pull_output_with_state(&mut gpiob.crh, PinState::Low)
-                    .erase(),
-            ),
-            Output::new(
-                pa15.into_push_pull_output_with_state(&mut gpioa.crh, PinState::Low)
-                    .erase(),
-            ),
-            Output::new(
-                gpioc
-                    .pc13
-                    .into_push_pull_output_with_state(&mut gpioc.crh, PinState::Low)
-                    .erase(),
-            ),
-        );
-
-        let scl = gpiob.pb10.into_alternate_open_drain(&mut gpiob.crh);
-        let sda = gpiob.pb11.into_alternate_open_drain(&mut gpiob.crh);
+        let led_heartbeat = board.led_heartbeat;
+        let led_cont = board.led_cont;
+        // Cannot fail, the group table of the board is checked at compile time
+        let pyro_controller = board::pyro_controller(board.pyro).unwrap();
+        let pyro_sense = board.sense;
+        let charge_monitor = ChargeMonitor::new(config::CHARGE);
+        let burnout_detector = BurnoutDetector::new(config::BURNOUT);
+        let liftoff_detector = LiftoffDetector::new(config::LIFTOFF);
 
         let _i2c = BlockingI2c::i2c2(
             cx.device.I2C2,
-            (scl, sda),
+            (board.i2c_scl, board.i2c_sda),
             Mode::Fast {
                 frequency: 400_000.hz(),
                 duty_cycle: stm32f1xx_hal::i2c::DutyCycle::Ratio16to9,
@@ -185,22 +206,45 @@ mod app {
             1000,
         );
         // let altitude_sensor = MPL3115A2::new(i2c, mpl3115::PressureAlt::Altitude).unwrap();
-        let can_aerospace = CANAerospaceLite::new(0xA, can_driver);
+        let can_aerospace = CANAerospaceLite::new(aero::NODE_ID, can_link);
+
+        let telemetry = Telemetry::new(TelemetryConfig {
+            status_period_ms: crate::TELEMETRY_STATUS_PERIOD_MS,
+            uptime_period_ms: crate::TELEMETRY_UPTIME_PERIOD_MS,
+        });
+
+        pyro_monitor::spawn().unwrap();
+        can_monitor::spawn().unwrap();
+        telemetry_publish::spawn().unwrap();
 
         // Init the static resources to use them later through RTIC
         (
             Shared {
                 // delay,
                 governor,
+                pyro_controller,
+                pyro_log: PyroLog::new(),
+                pyro_sequence: PyroSequence::new(),
+                journal: TransitionJournal::new(),
+                can_driver,
                 can_aerospace,
                 // altitude_sensor,
-                event_q: Q8::new(),
+                conditions: Conditions::default(),
             },
             Local {
                 timer,
-                pyro_controller,
                 led_heartbeat,
                 led_cont,
+                pyro_sense,
+                charge_monitor,
+                burnout_detector,
+                liftoff_detector,
+                dwell_timer: None,
+                lockout_timer: None,
+                sequence_timers: SequenceTimers::default(),
+                telemetry,
+                telemetry_timer: None,
+                fifo1,
             },
             init::Monotonics(mono),
         )
@@ -208,75 +252,7 @@ mod app {
 
     // > When no idle function is declared, the runtime sets the SLEEPONEXIT bit and then
     // > sends the microcontroller to sleep after running init.
-    #[idle(shared=[governor, event_q, can_aerospace], local=[active_pyro_state: PyroState = PyroState::IDLE, state: u32 = 0])]
-    fn idle(mut cx: idle::Context) -> ! {
-        let state_sim_value: &'static mut u32 = cx.local.state;
-        loop {
-            let _message =
-                cx.shared
-                    .can_aerospace
-                    .lock(|can_aerospace: &mut CANAerospaceLite<CANDriver>| {
-                        can_aerospace.read_message()
-                    });
-
-            let e = dequeue_event(&mut cx.shared.event_q).ok();
-            if let Some(event) = e {
-                match event {
-                    Event::StateInfo(s_event) => {
-                        state_handler::spawn(Some(s_event), None).unwrap();
-                    }
-                }
-            } else if *state_sim_value == 0 {
-                state_handler::spawn(None, Some(StateEnum::READY)).unwrap();
-                *state_sim_value += 1;
-            } else if *state_sim_value == 1 {
-                state_handler::spawn(None, Some(StateEnum::IGNITION)).unwrap();
-                *state_sim_value += 1;
-            } else if *state_sim_value == 2 {
-                state_handler::spawn(None, Some(StateEnum::PROPULSION)).unwrap();
-                *state_sim_value += 1;
-            } else {
-                state_handler::spawn(None, None).unwrap();
-            }
-
-            // TODO: set timeouts for states
-
-            // let e = block!(dequeue_event(&mut cx.shared.event_q)).unwrap();
-            // TODO: send state info using CANBUS
-
-            // cx.shared.governor.lock(|governor| {
-            //     // TODO: prioritize critical messages, create seperate functions for each state
-            //     match governor.get_current_state().id().try_into() {
-            //         Ok(StateEnum::IDLE) => {
-
-            //             governor.change_state_to(StateEnum::READY as u8);
-            //         }
-            //         Ok(StateEnum::READY) => {
-            //             // TODO: check continuity, continuity must be preserved, otherwise mission abort!
-            //             // TODO: ready to ignition transition
-            //             // TODO: ready to ignition transition; set ign0
-            //         }
-            //         Ok(StateEnum::IGNITION) => {
-            //             // TODO: check continuity
-            //             // TODO: clear ign0
-            //             // TODO: check lift-off; if it is then ignition is successfull, change state to propulsion
-            //         }
-            //         Ok(StateEnum::PROPULSION) => {
-            //             // TODO: set charge for 3 seconds (one-time)
-            //             // TODO: read accelerometer messages to detect burnout
-            //             // TODO: read can messages incase of any pyro-action
-            //         }
-            //         Ok(StateEnum::BURNOUT) => {
-            //             // TODO: read can messages incase of any pyro-action
-            //         }
-            //         Err(_) => {}
-            //     }
-            // });
-            delay_ms(500);
-
-            cortex_m::asm::nop();
-        }
-    }
+    // Events are handed to the state handler by the tasks that raise them, nothing is left to idle.
 
     #[task(binds = TIM1_UP, local= [led_heartbeat, timer])]
     fn tick(cx: tick::Context) {
@@ -288,94 +264,158 @@ mod app {
         timer.clear_update_interrupt_flag();
     }
 
-    #[task(binds = USB_LP_CAN_RX0, shared = [can_aerospace])]
+    #[task(binds = USB_LP_CAN_RX0, shared = [can_driver])]
     fn can_rx0(cx: can_rx0::Context) {
-        let mut can_aerospace = cx.shared.can_aerospace;
+        let mut can_driver = cx.shared.can_driver;
+        can_driver.lock(|can_driver: &mut CANDriver| {
+            let mut refusals = Vec::new();
+            can_driver.receive(|message: CANAerospaceMessage| dispatch(message, &mut refusals));
+            for refusal in &refusals {
+                can_driver.transmit(refusal);
+            }
+            if can_driver.link_pending() > 0 {
+                aero_link::spawn().ok();
+            }
+        });
+    }
 
-        can_aerospace.lock(|can| can.notify_receive_event());
+    #[task(binds = USB_HP_CAN_TX, shared = [can_driver])]
+    fn can_tx(mut cx: can_tx::Context) {
+        cx.shared
+            .can_driver
+            .lock(|can_driver: &mut CANDriver| can_driver.on_transmit_interrupt());
     }
 
-    fn delay_ms(ms: u32) {
-        let start_time = monotonics::now();
-        loop {
-            let instant: Instant<_> = monotonics::now();
-            let duration = instant.checked_duration_since(&start_time).unwrap();
-            let milliseconds: Milliseconds<u32> = duration.try_into().unwrap();
-            if milliseconds >= Milliseconds(ms) {
-                break;
-            }
+    // Commands are filtered into FIFO 1, which is drained here without the driver: they neither
+    // wait for the reception of flight data nor behind it
+    #[task(binds = CAN_RX1, priority = 2, shared = [can_driver], local = [fifo1])]
+    fn can_rx1(cx: can_rx1::Context) {
+        let fifo1: &mut RxFifo<Rx1<Can<CAN1>>> = cx.local.fifo1;
+        let mut can_driver = cx.shared.can_driver;
+        let mut refusals = Vec::new();
+        let mut passed = false;
+        fifo1.receive(
+            |message: CANAerospaceMessage| dispatch(message, &mut refusals),
+            |frame: Frame| {
+                can_driver.lock(|can_driver: &mut CANDriver| can_driver.pass_to_link(frame));
+                passed = true;
+            },
+        );
+        if passed {
+            aero_link::spawn().ok();
         }
+
+        let overruns = fifo1.take_overruns();
+        if overruns > 0 || !refusals.is_empty() {
+            can_driver.lock(|can_driver: &mut CANDriver| {
+                can_driver.count_overruns(overruns);
+                for refusal in &refusals {
+                    can_driver.transmit(refusal);
+                }
+            });
+        }
+    }
+
+    /// Lets CANaerospace-Lite take the frames the firmware does not handle itself, and transmits
+    /// the frames it queued in turn. None of its messages is used by the firmware.
+    #[task(shared = [can_driver, can_aerospace])]
+    fn aero_link(cx: aero_link::Context) {
+        let (mut can_driver, mut can_aerospace) = (cx.shared.can_driver, cx.shared.can_aerospace);
+        let pending = can_driver.lock(|can_driver: &mut CANDriver| can_driver.link_pending());
+        can_aerospace.lock(|can_aerospace: &mut CANAerospaceLite<AeroLink>| {
+            for _ in 0..pending {
+                can_aerospace.notify_receive_event();
+                let _message = can_aerospace.read_message();
+            }
+        });
+        can_driver.lock(|can_driver: &mut CANDriver| can_driver.flush_link());
     }
 
-    // the second parameter is generic: it can be any type that implements the `Mutex` trait
-    fn dequeue_event(mut consumer: impl Mutex<T = Q8<Event>>) -> nb::Result<Event, Infallible> {
-        if let Some(e) = consumer.lock(|q: &mut Q8<Event>| q.dequeue()) {
-            Ok(e)
-        } else {
-            Err(nb::Error::WouldBlock)
+    /// Passes a received message on. Commands are handed to the command handler right away. A
+    /// command the handler cannot take is refused instead of being dropped silently, the caller
+    /// transmits the refusal.
+    fn dispatch(
+        message: CANAerospaceMessage,
+        refusals: &mut Vec<CANAerospaceMessage, { crate::MAX_REFUSALS }>,
+    ) {
+        if let Some(request) = ServiceRequest::from_message(&message) {
+            // A dump already in progress is not restarted
+            match request {
+                ServiceRequest::PyroLog => pyro_log_dump::spawn(0).ok(),
+                ServiceRequest::Journal => journal_dump::spawn(0).ok(),
+            };
+        } else if let Some((command, message_code)) = Command::from_message(&message) {
+            if command_handler::spawn(command, message_code).is_err() {
+                refusals.push(command.refusal(message_code)).ok();
+            }
+        } else if let Some(data) = FlightData::from_message(&message) {
+            flight_monitor::spawn(data).ok();
         }
     }
 
-    use crate::tasks::pyro_task::pyro_handler;
-    use crate::tasks::state_task::state_handler;
+    use crate::tasks::command_task::{command_handler, command_response};
+    use crate::tasks::flight_task::flight_monitor;
+    use crate::tasks::monitor_task::{can_monitor, pyro_monitor};
+    use crate::tasks::pyro_task::{pyro_abort, pyro_handler, pyro_log_dump, pyro_pulse_end};
+    use crate::tasks::state_task::{
+        journal_dump, lockout_end, state_handler, state_timeout, transition_watchdog,
+    };
+    use crate::tasks::telemetry_task::telemetry_publish;
 
     // RTIC docs specify we can modularize the code by using these `extern` blocks.
     // This allows us to specify the tasks in other modules and still work within
     // RTIC's infrastructure.
     extern "Rust" {
-        #[task(capacity=5, priority=2, shared=[event_q], local=[pyro_controller])]
-        fn pyro_handler(
-            mut cx: pyro_handler::Context,
-            transition: &'static mut StateTransition<PyroState, 5>,
-        );
-        #[task(capacity=5, priority=10, shared=[event_q,governor], local=[led_cont])]
+        #[task(capacity=5, priority=2, shared=[pyro_controller, pyro_log, journal])]
+        fn pyro_handler(mut cx: pyro_handler::Context, state: PyroState, trigger: Option<Trigger>);
+        #[task(capacity=2, priority=2, shared=[pyro_controller, pyro_log])]
+        fn pyro_pulse_end(cx: pyro_pulse_end::Context, pulse: FirePulse);
+        #[task(priority=2, shared=[pyro_controller, pyro_log])]
+        fn pyro_abort(cx: pyro_abort::Context);
+        #[task(priority=2, shared=[can_driver, pyro_log])]
+        fn pyro_log_dump(mut cx: pyro_log_dump::Context, message: usize);
+        #[task(priority=2, shared=[governor, conditions, pyro_controller], local=[led_cont, pyro_sense, charge_monitor])]
+        fn pyro_monitor(mut cx: pyro_monitor::Context);
+        #[task(priority=2, shared=[can_driver, conditions])]
+        fn can_monitor(mut cx: can_monitor::Context);
+        #[task(capacity=4, priority=2, shared=[governor], local=[burnout_detector, liftoff_detector])]
+        fn flight_monitor(mut cx: flight_monitor::Context, data: FlightData);
+        #[task(capacity=5, priority=10, shared=[can_driver, governor, conditions, journal, pyro_sequence], local=[dwell_timer, lockout_timer, sequence_timers])]
         fn state_handler(
-            mut cx: state_handler::Context,
-            event: Option<StateEvent>,
+            cx: state_handler::Context,
+            event: Option<Event>,
             new_state: Option<StateEnum>,
         );
+        #[task(capacity=2, priority=2)]
+        fn state_timeout(cx: state_timeout::Context, state: StateEnum);
+        #[task(priority=2, shared=[can_driver, journal])]
+        fn journal_dump(cx: journal_dump::Context, message: usize);
+        #[task(priority=2)]
+        fn transition_watchdog(cx: transition_watchdog::Context);
+        #[task(priority=2, shared=[conditions])]
+        fn lockout_end(mut cx: lockout_end::Context);
+        #[task(capacity=2, priority=10, shared=[governor, conditions, pyro_sequence])]
+        fn command_handler(mut cx: command_handler::Context, command: Command, message_code: u8);
+        #[task(capacity=2, priority=2, shared=[can_driver, governor, conditions, pyro_controller])]
+        fn command_response(
+            mut cx: command_response::Context,
+            command: Command,
+            message_code: u8,
+            ack: u8,
+        );
+        #[task(capacity=3, priority=2, shared=[can_driver, governor, conditions, pyro_controller], local=[telemetry, telemetry_timer])]
+        fn telemetry_publish(mut cx: telemetry_publish::Context);
 
     }
 }
 
-fn initialize_pyro_controller(
-    charge: PINErasedPP,
-    discharge: PINErasedPPInv,
-    pyro1: PINErasedPP,
-    pyro2: PINErasedPP,
-    ignition: PINErasedPP,
-) -> PyroController<3> {
-    let mut pyro_controller = PyroController::<3>::new(charge, discharge);
-    pyro_controller
-        .add_channel(PyroChannel {
-            name: PyroChannelName::Pyro1,
-            pin: pyro1,
-        })
-        .unwrap();
-
-    pyro_controller
-        .add_channel(PyroChannel {
-            name: PyroChannelName::Ignition,
-            pin: ignition,
-        })
-        .unwrap();
-
-    pyro_controller
-        .add_channel(PyroChannel {
-            name: PyroChannelName::Pyro2,
-            pin: pyro2,
-        })
-        .unwrap();
-    pyro_controller
-}
-
 fn initialize_canbus(
     can: CAN1,
     usb: USB,
     can_rx_pin: PA11<Input<Floating>>,
     can_tx_pin: PA12<Alternate<PushPull>>,
     afio: &mut afio::Parts,
-) -> CANDriver {
+) -> (Tx<Can<CAN1>>, Rx0<Can<CAN1>>, Rx1<Can<CAN1>>) {
     let can_peripheral = Can::new(can, usb);
     can_peripheral.assign_pins((can_tx_pin, can_rx_pin), &mut afio.mapr);
 
@@ -383,13 +423,22 @@ fn initialize_canbus(
 
     // APB1 (PCLK1): 16MHz, Bit rate: 1000kBit/s, Sample Point 87.5%
     // Value was calculated with http://www.bittiming.can-wiki.info/
-    can.modify_config().set_bit_timing(0x001c_0000);
+    {
+        let mut config = can.modify_config();
+        config.set_bit_timing(0x001c_0000);
+        // The controller rejoins the bus by itself after 128 occurrences of 11 recessive bits
+        config.set_automatic_bus_off_management(true);
+    }
 
-    can.modify_filters().enable_bank(0, Mask32::accept_all());
+    // Only the identifiers this node consumes are accepted, commands through FIFO 1
+    can_driver::enable_filters(&mut can.modify_filters());
 
     // Sync to the bus and start normal operation.
-    can.enable_interrupts(Interrupts::TRANSMIT_MAILBOX_EMPTY | Interrupts::FIFO0_MESSAGE_PENDING);
+    can.enable_interrupts(
+        Interrupts::TRANSMIT_MAILBOX_EMPTY
+            | Interrupts::FIFO0_MESSAGE_PENDING
+            | Interrupts::FIFO1_MESSAGE_PENDING,
+    );
     block!(can.enable()).unwrap();
-    let (can_tx, can_rx) = can.split();
-    CANDriver::new(can_tx, can_rx)
+    can.split()
 }
@@ -0,0 +1,112 @@
+//! Panic handler that safes the pyro circuit, keeps the panic message for the next boot and resets
+//! the board.
+
+use core::{
+    fmt::{self, Write},
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr,
+};
+
+use can_aerospace_lite::{message::CANAerospaceMessage, types::DataType};
+use cortex_m::peripheral::SCB;
+use pike_enginecontrol::aero::message;
+
+/// CANaerospace debug service ID the panic report is sent with after the next boot.
+pub const PANIC_REPORT_ID: u16 = 1900;
+
+const RECORD_MAGIC: u32 = 0xDEAD_BEEF;
+const MESSAGE_LEN: usize = 96;
+
+#[derive(Clone, Copy)]
+pub struct PanicRecord {
+    magic: u32,
+    line: u32,
+    len: usize,
+    message: [u8; MESSAGE_LEN],
+}
+
+// The runtime only zeroes `.bss` and copies `.data`, the `.panic_record` output section of
+// `memory.x` is placed after them and left alone, so the record survives a reset (but not a power
+// cycle)
+#[link_section = ".panic_record"]
+static mut PANIC_RECORD: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+impl PanicRecord {
+    /// The line number first, then the formatted panic message in chunks of 4 characters.
+    pub fn messages(&self) -> impl Iterator<Item = CANAerospaceMessage> + '_ {
+        let line = message(PANIC_REPORT_ID, 0, DataType::ULONG(self.line));
+        let text = self.message[..self.len.min(MESSAGE_LEN)]
+            .chunks(4)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut data = [0; 4];
+                data[..chunk.len()].copy_from_slice(chunk);
+                message(PANIC_REPORT_ID, (index + 1) as u8, DataType::ACHAR4(data))
+            });
+        core::iter::once(line).chain(text)
+    }
+}
+
+/// Returns the record of the panic before the last reset, if there was one, and clears it.
+pub fn take_record() -> Option<PanicRecord> {
+    unsafe {
+        let record_ptr = ptr::addr_of_mut!(PANIC_RECORD).cast::<PanicRecord>();
+        // Only the magic is trusted to be initialized before it is checked
+        let magic = ptr::addr_of!((*record_ptr).magic);
+        if ptr::read_volatile(magic) != RECORD_MAGIC {
+            return None;
+        }
+        let record = ptr::read_volatile(record_ptr);
+        ptr::write_volatile(ptr::addr_of_mut!((*record_ptr).magic), 0);
+        Some(record)
+    }
+}
+
+struct MessageWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // The message is truncated silently, the panic handler must not fail
+        for byte in s.bytes() {
+            if self.len >= self.buffer.len() {
+                break;
+            }
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+#[inline(never)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    crate::board::safe_pyro_outputs();
+
+    let mut record = PanicRecord {
+        magic: RECORD_MAGIC,
+        line: info.location().map_or(0, |location| location.line()),
+        len: 0,
+        message: [0; MESSAGE_LEN],
+    };
+    let mut writer = MessageWriter {
+        buffer: &mut record.message,
+        len: 0,
+    };
+    write!(writer, "{}", info).ok();
+    record.len = writer.len;
+    unsafe {
+        ptr::write_volatile(
+            ptr::addr_of_mut!(PANIC_RECORD).cast::<PanicRecord>(),
+            record,
+        );
+    }
+
+    // The board starts over safe, the record is reported by `init`
+    SCB::sys_reset()
+}
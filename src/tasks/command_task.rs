@@ -0,0 +1,87 @@
+use core::convert::TryInto;
+use pike_enginecontrol::{
+    aero::{command_ack, Command},
+    can_driver::CANDriver,
+    engine::{command_request, find_transition, Conditions, TransitionError, Trigger},
+    sequence::PyroSequence,
+    StateEnum,
+};
+use rtic::{mutex_prelude::*, time::duration::Milliseconds};
+use state_governor::{state::State, Governor};
+
+use crate::{
+    app::{command_handler, command_response, state_handler},
+    board::PyroController,
+    tasks::{pyro_task::DUMP_RETRY_MS, telemetry_task::node_status},
+};
+
+/// Acknowledges a command and hands the state it requests to the state handler. Runs at the
+/// priority of the state handler, so nothing changes between the check and the state change.
+pub(crate) fn command_handler(
+    mut cx: command_handler::Context,
+    command: Command,
+    message_code: u8,
+) {
+    let sequence: &PyroSequence = cx.shared.pyro_sequence;
+
+    let current_state: Result<StateEnum, _> = cx
+        .shared
+        .governor
+        .lock(|g: &mut Governor<6>| g.get_current_state().id())
+        .try_into();
+    let current_state = match current_state {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+    let conditions = cx.shared.conditions.lock(|c: &mut Conditions| *c);
+
+    let ack = match command_request(command, current_state) {
+        None if command == Command::Status => command_ack::ACK,
+        None => command_ack::NOT_ALLOWED,
+        // Only ABORT preempts a transition in progress
+        Some(state) if sequence.in_progress() && state != StateEnum::ABORT => command_ack::BUSY,
+        Some(state) => {
+            let lookup = find_transition(current_state, Trigger::Request(state), &conditions);
+            // Refused requests are passed on as well, the state handler journals them
+            if state_handler::spawn(None, Some(state)).is_err() {
+                command_ack::BUSY
+            } else {
+                match lookup {
+                    Ok(_) => command_ack::ACK,
+                    Err(TransitionError::NotAllowed) => command_ack::NOT_ALLOWED,
+                    Err(TransitionError::GuardFailed) => command_ack::GUARD_FAILED,
+                }
+            }
+        }
+    };
+    command_response::spawn(command, message_code, ack).ok();
+}
+
+/// Sends the response to a command with the node status at the time it is sent: system state,
+/// pyro state and status flags, as in the telemetry. Retried later whenever the transmit queue is
+/// full.
+pub(crate) fn command_response(
+    mut cx: command_response::Context,
+    command: Command,
+    message_code: u8,
+    ack: u8,
+) {
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+    let status = match node_status(
+        &mut cx.shared.governor,
+        &mut cx.shared.conditions,
+        controller,
+    ) {
+        Some(status) => status.to_data(),
+        None => return,
+    };
+
+    let response = command.response(message_code, ack, [status[0], status[1], status[2]]);
+    let sent = cx
+        .shared
+        .can_driver
+        .lock(|driver: &mut CANDriver| driver.try_transmit(&response));
+    if !sent {
+        command_response::spawn_after(Milliseconds(DUMP_RETRY_MS), command, message_code, ack).ok();
+    }
+}
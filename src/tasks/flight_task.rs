@@ -0,0 +1,51 @@
+use core::convert::TryInto;
+use pike_enginecontrol::{
+    aero::FlightData,
+    burnout::BurnoutDetector,
+    event::{Event, StateEvent},
+    liftoff::LiftoffDetector,
+    StateEnum,
+};
+use rtic::mutex_prelude::*;
+use state_governor::Governor;
+
+use crate::{
+    app::flight_monitor,
+    tasks::{post_event, uptime_ms},
+};
+
+/// Feeds the flight data received over CAN to the lift-off detector during ignition and to the
+/// burnout detector while the motor burns.
+pub(crate) fn flight_monitor(mut cx: flight_monitor::Context, data: FlightData) {
+    let liftoff: &mut LiftoffDetector = cx.local.liftoff_detector;
+    let burnout: &mut BurnoutDetector = cx.local.burnout_detector;
+
+    let state: Result<StateEnum, _> = cx
+        .shared
+        .governor
+        .lock(|g: &mut Governor<6>| g.get_current_state().id().try_into());
+    let now = uptime_ms();
+
+    let event = match (state, data) {
+        (Ok(StateEnum::IGNITION), _) => {
+            burnout.reset();
+            liftoff.update(now, data).then(|| StateEvent::Liftoff)
+        }
+        (Ok(StateEnum::PROPULSION), FlightData::Acceleration(acceleration)) => {
+            liftoff.reset();
+            burnout
+                .update(now, acceleration)
+                .then(|| StateEvent::Burnout)
+        }
+        (Ok(StateEnum::PROPULSION), _) => None,
+        _ => {
+            liftoff.reset();
+            burnout.reset();
+            None
+        }
+    };
+
+    if let Some(event) = event {
+        post_event(Event::StateInfo(event));
+    }
+}
@@ -1,2 +1,99 @@
+pub mod command_task;
+pub mod flight_task;
+pub mod monitor_task;
 pub mod pyro_task;
 pub mod state_task;
+pub mod telemetry_task;
+
+use core::{
+    cell::Cell,
+    convert::TryInto,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::interrupt::{self, Mutex as InterruptMutex};
+use pike_enginecontrol::{
+    engine::Trigger,
+    event::{Event, Fault},
+    journal::{Journal, JournalResult, JournalTransition},
+};
+use rtic::{time::duration::Milliseconds, Mutex};
+
+use crate::app::{pyro_abort, state_handler};
+
+pub(crate) type TransitionJournal = Journal<{ crate::JOURNAL_LEN }>;
+
+// Events the state handler could not take, reported by it the next time it runs
+static DROPPED_EVENTS: AtomicU32 = AtomicU32::new(0);
+
+// The first fault the state handler could not take, it is handled the next time the handler runs
+static PENDING_FAULT: InterruptMutex<Cell<Option<Fault>>> = InterruptMutex::new(Cell::new(None));
+
+// Set by the state handler on every state change, see `engine::in_flight`
+static IN_FLIGHT: AtomicBool = AtomicBool::new(false);
+
+/// Hands `event` to the state handler, which reports it and takes the transition it leads to.
+///
+/// Every producer runs below the priority of the state handler, which therefore takes each event
+/// before the next one is posted, its queue only fills up with polls and requests. An event that
+/// does not fit anyway is counted. A fault is never dropped: it is kept for the next run of the
+/// handler, and on the ground the pyro controller is safed right away.
+pub(crate) fn post_event(event: Event) {
+    if state_handler::spawn(Some(event), None).is_ok() {
+        return;
+    }
+    match event {
+        Event::Fault(fault) => {
+            interrupt::free(|cs| {
+                let pending = PENDING_FAULT.borrow(cs);
+                if pending.get().is_none() {
+                    pending.set(Some(fault));
+                }
+            });
+            if !IN_FLIGHT.load(Ordering::Relaxed) {
+                pyro_abort::spawn().ok();
+            }
+        }
+        _ => {
+            DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Tells [`post_event`] whether a fault may safe the pyro controller.
+pub(crate) fn set_in_flight(in_flight: bool) {
+    IN_FLIGHT.store(in_flight, Ordering::Relaxed);
+}
+
+/// The fault kept by [`post_event`], if any.
+pub(crate) fn take_pending_fault() -> Option<Fault> {
+    interrupt::free(|cs| PENDING_FAULT.borrow(cs).take())
+}
+
+/// Number of events dropped by [`post_event`] since the previous call.
+pub(crate) fn take_dropped_events() -> u32 {
+    DROPPED_EVENTS.swap(0, Ordering::Relaxed)
+}
+
+/// Milliseconds since boot, taken from the monotonic.
+pub(crate) fn uptime_ms() -> u32 {
+    let since_boot: Milliseconds<u32> = crate::app::monotonics::now()
+        .duration_since_epoch()
+        .try_into()
+        .unwrap();
+    since_boot.0
+}
+
+/// Journals a transition at the current uptime, and prints it on the debug console if enabled.
+pub(crate) fn record_transition(
+    journal: &mut impl Mutex<T = TransitionJournal>,
+    transition: JournalTransition,
+    trigger: Option<Trigger>,
+    result: JournalResult,
+) {
+    let now = uptime_ms();
+    let _entry = journal
+        .lock(|journal: &mut TransitionJournal| journal.record(now, transition, trigger, result));
+    // Semihosting halts the core without a debugger attached, only enabled for bench tests
+    #[cfg(feature = "debug-console")]
+    cortex_m_semihosting::hprintln!("{}", _entry).ok();
+}
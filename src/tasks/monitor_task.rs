@@ -0,0 +1,86 @@
+use core::convert::TryInto;
+use pike_enginecontrol::{
+    can_driver::CANDriver,
+    charge::{ChargeMonitor, ChargeStatus},
+    config::MONITOR_PERIOD_MS,
+    engine::Conditions,
+    event::{Event, StateEvent},
+    pyro::PyroState,
+    StateEnum,
+};
+use rtic::{mutex_prelude::*, time::duration::Milliseconds};
+use state_governor::Governor;
+
+use crate::{
+    app::{can_monitor, pyro_monitor},
+    board::{PyroController, SensePins, PYRO_CHANNEL_NAMES},
+    tasks::{post_event, uptime_ms},
+};
+
+pub(crate) fn pyro_monitor(mut cx: pyro_monitor::Context) {
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+    let sense: &SensePins = cx.local.pyro_sense;
+    let charge_monitor: &mut ChargeMonitor = cx.local.charge_monitor;
+
+    // Continuity changes of an armed system are reported, READY disarms when a channel loses it
+    let armed = cx.shared.governor.lock(|g: &mut Governor<6>| {
+        matches!(
+            g.get_current_state().id().try_into(),
+            Ok(StateEnum::READY) | Ok(StateEnum::IGNITION)
+        )
+    });
+
+    for channel in PYRO_CHANNEL_NAMES {
+        if let Some(continuity) = sense.read_channel(channel) {
+            if let Some(continuity) = controller.set_continuity(channel, continuity) {
+                if armed {
+                    post_event(Event::StateInfo(StateEvent::ContinuityChanged(
+                        channel, continuity,
+                    )));
+                }
+            }
+        }
+    }
+
+    let continuity_ok = controller.continuity_ok();
+    if continuity_ok {
+        cx.local.led_cont.set_high();
+    } else {
+        cx.local.led_cont.set_low();
+    }
+    cx.shared
+        .conditions
+        .lock(|c: &mut Conditions| c.continuity_ok = continuity_ok);
+
+    // The board has no charge sense, see `SensePins`
+    if controller.get_state() == PyroState::CHARGING {
+        if let Some(ChargeStatus::Charged) = charge_monitor.update_timed(uptime_ms()) {
+            post_event(Event::StateInfo(StateEvent::ChargeComplete));
+        }
+    } else {
+        charge_monitor.reset();
+    }
+
+    pyro_monitor::spawn_after(Milliseconds(MONITOR_PERIOD_MS)).ok();
+}
+
+/// Watches the error counters of the CAN controller, a degraded or restored link is reported to
+/// the state machine.
+pub(crate) fn can_monitor(mut cx: can_monitor::Context) {
+    let change = cx
+        .shared
+        .can_driver
+        .lock(|driver: &mut CANDriver| driver.check_errors());
+    if let Some(degraded) = change {
+        cx.shared
+            .conditions
+            .lock(|c: &mut Conditions| c.link_degraded = degraded);
+        let event = if degraded {
+            StateEvent::LinkDegraded
+        } else {
+            StateEvent::LinkRestored
+        };
+        post_event(Event::StateInfo(event));
+    }
+    can_monitor::spawn_after(Milliseconds(MONITOR_PERIOD_MS)).ok();
+}
@@ -1,41 +1,136 @@
-use crate::app::pyro_handler;
-use heapless::mpmc::Q8;
+use crate::app::{pyro_abort, pyro_handler, pyro_log_dump, pyro_pulse_end};
 use pike_enginecontrol::{
-    event::{Event, StateEvent},
-    pyro::{PyroController, PyroError, PyroState},
-    state::StateTransition,
+    aero::SERVICE_RESPONSE_ID,
+    can_driver::CANDriver,
+    engine::Trigger,
+    event::{Event, Fault, PyroEvent},
+    journal::{JournalResult, JournalTransition},
+    machine,
+    pyro::{FirePulse, PyroChannelName, PyroError, PyroState},
+    pyro_log::{ActionOutcome, PyroAction, PyroLog},
 };
-use rtic::mutex_prelude::*;
+use rtic::{mutex_prelude::*, time::duration::Milliseconds};
 
+use crate::{
+    board::{PyroController, PYRO_CHANNELS},
+    tasks::{post_event, record_transition, uptime_ms},
+};
+
+pub(crate) type Log = PyroLog<{ crate::PYRO_LOG_LEN }>;
+
+// Gives the transmit queue time to drain before a log dump continues
+pub(crate) const DUMP_RETRY_MS: u32 = 10;
+
+/// Executes one step of a pyro transition, the state handler advances the transition once the
+/// step is reported. `trigger` released the step and is only journaled.
 pub(crate) fn pyro_handler(
     mut cx: pyro_handler::Context,
-    transition: &mut StateTransition<PyroState, 5>,
+    state: PyroState,
+    trigger: Option<Trigger>,
 ) {
-    let controller: &mut PyroController<3> = cx.local.pyro_controller;
-    // cx.local.led_cont.toggle();
-    if !transition.finished() {
-        let state = transition.state().unwrap();
-
-        match state {
-            PyroState::IDLE => {
-                controller.continuous_state();
-            }
-            PyroState::CHARGING => {
-                controller.charge();
-            }
-            PyroState::DISCHARGING => {
-                controller.discharge();
-            }
-            PyroState::READY => {
-                controller.closed_state(); // Disconnects the connection between battery and discharge circuit
-            }
-            PyroState::FIRING(channel) => {
-                controller.fire(channel);
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+    let log: &mut Log = cx.shared.pyro_log;
+
+    let result = change_state(controller, log, state);
+    if let Ok(true) = result {
+        crate::app::telemetry_publish::spawn().ok();
+    }
+    record_transition(
+        &mut cx.shared.journal,
+        JournalTransition::Pyro(state),
+        trigger,
+        match result {
+            Ok(_) => JournalResult::Done,
+            Err(_) => JournalResult::Failed,
+        },
+    );
+
+    // Only a pulse started by this step is ended here, a repeated step must not schedule another
+    // end for a pulse that is already timed.
+    if let (Ok(true), Some(pulse)) = (result, controller.active_pulse()) {
+        // An e-match must never stay energised, end the pulse right away if it cannot be scheduled.
+        if crate::app::pyro_pulse_end::spawn_after(Milliseconds(pulse.width_ms), pulse).is_err() {
+            end_pulse(controller, log, pulse).ok();
+        }
+    }
+    post_event(machine::step_event(state, result));
+}
+
+pub(crate) fn pyro_pulse_end(cx: pyro_pulse_end::Context, pulse: FirePulse) {
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+    match end_pulse(controller, cx.shared.pyro_log, pulse) {
+        Ok(true) => post_event(Event::Pyro(PyroEvent::PulseEnded(pulse.channel))),
+        Ok(false) => {}
+        Err(e) => post_event(Event::Fault(Fault::Pyro(e))),
+    }
+}
+
+/// Safes the pyro controller at once, whatever it is doing.
+pub(crate) fn pyro_abort(cx: pyro_abort::Context) {
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+    controller.safe_state();
+    cx.shared.pyro_log.record(
+        uptime_ms(),
+        PyroAction::State(PyroState::DISCHARGING),
+        ActionOutcome::Done,
+    );
+}
+
+/// Sends the pyro log starting at dump message `message`, continues later whenever the transmit
+/// queue is full. Entries recorded while the dump is in progress can shift the remaining messages.
+pub(crate) fn pyro_log_dump(mut cx: pyro_log_dump::Context, mut message: usize) {
+    let log: &mut Log = cx.shared.pyro_log;
+    let sent = cx.shared.can_driver.lock(|driver: &mut CANDriver| {
+        while let Some(frame) = log.dump_message(SERVICE_RESPONSE_ID, message) {
+            if !driver.transmit_bulk(&frame) {
+                return false;
             }
+            message += 1;
+        }
+        true
+    });
+    if !sent {
+        pyro_log_dump::spawn_after(Milliseconds(DUMP_RETRY_MS), message).ok();
+    }
+}
+
+/// Changes the pyro state and records the attempt, and the channels it enabled, in the log.
+pub(crate) fn change_state(
+    controller: &mut PyroController,
+    log: &mut Log,
+    state: PyroState,
+) -> Result<bool, PyroError> {
+    let result = controller.change_state(state);
+    let now = uptime_ms();
+    if result != Ok(false) {
+        log.record(now, PyroAction::State(state), ActionOutcome::from(&result));
+    }
+    if let (Ok(true), PyroState::FIRING(_)) = (&result, state) {
+        for channel in controller.active_channels() {
+            log.record(now, PyroAction::ChannelOn(channel), ActionOutcome::Done);
+        }
+    }
+    result
+}
+
+/// Ends `pulse` and records every channel it disabled in the log.
+fn end_pulse(
+    controller: &mut PyroController,
+    log: &mut Log,
+    pulse: FirePulse,
+) -> Result<bool, PyroError> {
+    let active: heapless::Vec<PyroChannelName, PYRO_CHANNELS> =
+        controller.active_channels().collect();
+    let result = controller.end_pulse(pulse);
+    let now = uptime_ms();
+    if !matches!(result, Ok(false)) {
+        for channel in active {
+            log.record(
+                now,
+                PyroAction::ChannelOff(channel),
+                ActionOutcome::from(&result),
+            );
         }
-        transition.next();
-        cx.shared.event_q.lock(|q: &mut Q8<Event>| {
-            q.enqueue(Event::StateInfo(StateEvent::Pyro(state))).ok();
-        });
     }
+    result
 }
@@ -1,100 +1,209 @@
 use core::convert::TryInto;
-use heapless::{mpmc::Q8, Vec};
 use pike_enginecontrol::{
-    event::{Event, StateEvent},
+    aero::SERVICE_RESPONSE_ID,
+    can_driver::CANDriver,
+    config::{DWELL_LIMITS, HANGFIRE_LOCKOUT_MS, TRANSITION_STEP_TIMEOUT_MS},
+    engine::{in_flight, max_dwell_ms, Conditions, Trigger},
+    event::{Event, Fault, StateEvent},
+    journal::{JournalResult, JournalTransition},
+    machine::{self, MachineRuntime},
     pyro::PyroState,
-    state::{MovingState, StateTransition},
+    sequence::{PyroSequence, SequenceRuntime},
     StateEnum,
 };
 
-use rtic::mutex_prelude::*;
+use rtic::{mutex_prelude::*, time::duration::Milliseconds, Mutex};
 use state_governor::{state::State, Governor};
 
-use crate::{app::state_handler, tasks::pyro_task::pyro_handler};
+use crate::{
+    app::{
+        journal_dump, lockout_end, pyro_handler, state_handler, state_timeout, transition_watchdog,
+    },
+    tasks::{
+        post_event, pyro_task::DUMP_RETRY_MS, record_transition, set_in_flight,
+        take_dropped_events, take_pending_fault, uptime_ms, TransitionJournal,
+    },
+};
+
+/// Timers of the pyro sequence, they belong to the state handler.
+#[derive(Default)]
+pub struct SequenceTimers {
+    watchdog: Option<transition_watchdog::SpawnHandle>,
+    poll: Option<state_handler::SpawnHandle>,
+}
+
+/// The firmware side of the state machine, see [`machine::process`].
+struct HandlerRuntime<'a, G, J, D> {
+    state: StateEnum,
+    timers: &'a mut SequenceTimers,
+    dwell_timer: &'a mut Option<state_timeout::SpawnHandle>,
+    lockout_timer: &'a mut Option<lockout_end::SpawnHandle>,
+    governor: G,
+    journal: J,
+    can_driver: D,
+}
+
+impl<G, J, D> SequenceRuntime for HandlerRuntime<'_, G, J, D> {
+    fn now_ms(&self) -> u32 {
+        uptime_ms()
+    }
+
+    fn spawn_step(&mut self, step: PyroState, trigger: Option<Trigger>) -> bool {
+        pyro_handler::spawn(step, trigger).is_ok()
+    }
+
+    /// Every step, including the wait for its guard, has to complete within the timeout,
+    /// otherwise the transition is reported as stuck.
+    fn arm_watchdog(&mut self) {
+        self.cancel_watchdog();
+        self.timers.watchdog =
+            transition_watchdog::spawn_after(Milliseconds(TRANSITION_STEP_TIMEOUT_MS)).ok();
+    }
+
+    fn cancel_watchdog(&mut self) {
+        if let Some(watchdog) = self.timers.watchdog.take() {
+            watchdog.cancel().ok();
+        }
+    }
+
+    fn schedule_poll(&mut self, after_ms: u32) {
+        self.cancel_poll();
+        self.timers.poll = state_handler::spawn_after(Milliseconds(after_ms), None, None).ok();
+    }
+
+    fn cancel_poll(&mut self) {
+        if let Some(poll) = self.timers.poll.take() {
+            poll.cancel().ok();
+        }
+    }
+}
+
+impl<G, J, D> MachineRuntime for HandlerRuntime<'_, G, J, D>
+where
+    G: Mutex<T = Governor<6>>,
+    J: Mutex<T = TransitionJournal>,
+    D: Mutex<T = CANDriver>,
+{
+    fn state(&self) -> StateEnum {
+        self.state
+    }
+
+    fn enter(&mut self, state: StateEnum) {
+        self.state = state;
+        set_in_flight(in_flight(state));
+        self.governor
+            .lock(|g: &mut Governor<6>| g.change_state_to(state as u8));
+        crate::app::telemetry_publish::spawn().ok();
+        if let Some(timer) = self.dwell_timer.take() {
+            timer.cancel().ok();
+        }
+        if let Some(max_ms) = max_dwell_ms(DWELL_LIMITS, state) {
+            *self.dwell_timer = state_timeout::spawn_after(Milliseconds(max_ms), state).ok();
+        }
+    }
+
+    fn record(
+        &mut self,
+        transition: JournalTransition,
+        trigger: Option<Trigger>,
+        result: JournalResult,
+    ) {
+        record_transition(&mut self.journal, transition, trigger, result);
+    }
 
-pub(crate) unsafe fn state_handler(
-    mut cx: state_handler::Context,
-    event: Option<StateEvent>,
+    fn abort_pyro(&mut self) {
+        crate::app::pyro_abort::spawn().ok();
+    }
+
+    fn start_lockout(&mut self) {
+        // Another hang-fire restarts the lockout
+        if let Some(timer) = self.lockout_timer.take() {
+            timer.cancel().ok();
+        }
+        *self.lockout_timer = lockout_end::spawn_after(Milliseconds(HANGFIRE_LOCKOUT_MS)).ok();
+    }
+
+    fn report(&mut self, event: &Event) {
+        if let Some(report) = event.report() {
+            self.can_driver
+                .lock(|driver: &mut CANDriver| driver.transmit(&report));
+        }
+    }
+}
+
+/// Handles an event, a state request, or without either re-evaluates the guard of the waiting
+/// pyro step. A fault kept while the handler was busy is handled first.
+pub(crate) fn state_handler(
+    cx: state_handler::Context,
+    event: Option<Event>,
     new_state: Option<StateEnum>,
 ) {
-    // let transition: &'static mut TransitionContext = cx.local.context;
-    static mut TRANSITION: StateTransition<PyroState, 5> = StateTransition {
-        current_index: 0xFF,
-        transition_path: Vec::new(),
+    let shared = cx.shared;
+    let sequence: &mut PyroSequence = shared.pyro_sequence;
+    let mut governor = shared.governor;
+    let mut conditions = shared.conditions;
+
+    let current_state: Result<StateEnum, _> = governor
+        .lock(|g: &mut Governor<6>| g.get_current_state().id())
+        .try_into();
+    let current_state = match current_state {
+        Ok(state) => state,
+        Err(_) => return,
     };
-    static mut COMMITTED_SYS_STATE: Option<StateEnum> = None;
-
-    if !TRANSITION.finished() {
-        if let Some(fired_event) = event {
-            if let Some(current_state) = TRANSITION.state() {
-                let required_events = current_state.get_required_events();
-                if required_events.is_empty() || required_events.contains(&fired_event) {
-                    crate::app::pyro_handler::spawn(&mut TRANSITION)
-                        .ok()
-                        .unwrap();
-                }
-            }
-        }
-    } else {
-        if let Some(commited_state) = COMMITTED_SYS_STATE {
-            cx.shared
-                .governor
-                .lock(|g: &mut Governor<5>| g.change_state_to(commited_state as u8));
-
-            if commited_state == StateEnum::PROPULSION {
-                cx.local.led_cont.toggle();
-            }
-            COMMITTED_SYS_STATE = None;
-            TRANSITION.reset();
+    let mut runtime = HandlerRuntime {
+        state: current_state,
+        timers: cx.local.sequence_timers,
+        dwell_timer: cx.local.dwell_timer,
+        lockout_timer: cx.local.lockout_timer,
+        governor,
+        journal: shared.journal,
+        can_driver: shared.can_driver,
+    };
+
+    let dropped = take_dropped_events();
+    if dropped > 0 {
+        let report = Event::dropped_report(dropped);
+        runtime
+            .can_driver
+            .lock(|driver: &mut CANDriver| driver.transmit(&report));
+    }
+    conditions.lock(|c: &mut Conditions| {
+        if let Some(fault) = take_pending_fault() {
+            machine::process(&mut runtime, sequence, c, Some(Event::Fault(fault)), None);
         }
+        machine::process(&mut runtime, sequence, c, event, new_state)
+    });
+}
 
-        if let Some(next_state) = new_state {
-            match next_state {
-                StateEnum::READY => {
-                    TRANSITION.add_state(PyroState::CHARGING);
-                    TRANSITION.add_state(PyroState::READY);
-                    TRANSITION.start();
-                    COMMITTED_SYS_STATE = Some(StateEnum::READY);
-
-                    crate::app::pyro_handler::spawn(&mut TRANSITION)
-                        .ok()
-                        .unwrap();
-                }
-                StateEnum::IGNITION => {
-                    TRANSITION.add_state(PyroState::IDLE);
-                    TRANSITION.start();
-                    COMMITTED_SYS_STATE = Some(StateEnum::IGNITION);
-                    crate::app::pyro_handler::spawn(&mut TRANSITION)
-                        .ok()
-                        .unwrap();
-                }
-                StateEnum::PROPULSION => {
-                    TRANSITION.add_state(PyroState::CHARGING);
-                    TRANSITION.add_state(PyroState::READY);
-                    TRANSITION.start();
-                    COMMITTED_SYS_STATE = Some(StateEnum::PROPULSION);
-                    crate::app::pyro_handler::spawn(&mut TRANSITION)
-                        .ok()
-                        .unwrap();
+pub(crate) fn state_timeout(_: state_timeout::Context, state: StateEnum) {
+    post_event(Event::StateInfo(StateEvent::Timeout(state)));
+}
+
+pub(crate) fn transition_watchdog(_: transition_watchdog::Context) {
+    post_event(Event::Fault(Fault::TransitionStuck));
+}
+
+/// Sends the journal starting at dump message `message`, continues later whenever the transmit
+/// queue is full.
+pub(crate) fn journal_dump(cx: journal_dump::Context, mut message: usize) {
+    let sent = (cx.shared.can_driver, cx.shared.journal).lock(
+        |driver: &mut CANDriver, journal: &mut TransitionJournal| {
+            while let Some(frame) = journal.dump_message(SERVICE_RESPONSE_ID, message) {
+                if !driver.transmit_bulk(&frame) {
+                    return false;
                 }
-                StateEnum::BURNOUT => todo!(),
-                _ => {}
+                message += 1;
             }
-            if next_state == StateEnum::READY {}
-        } else {
-            // let current_state: StateEnum = cx
-            //     .shared
-            //     .governor
-            //     .lock(|g: &mut Governor<5>| g.get_current_state().id())
-            //     .try_into()
-            //     .unwrap();
-            // match current_state {
-            //     StateEnum::IDLE => crate::app::pyro_handler::spawn(PyroState::IDLE).unwrap(),
-            //     StateEnum::READY => crate::app::pyro_handler::spawn(PyroState::READY).unwrap(),
-            //     StateEnum::IGNITION => {}
-            //     StateEnum::PROPULSION => {}
-            //     StateEnum::BURNOUT => {}
-            // }
-        }
+            true
+        },
+    );
+    if !sent {
+        journal_dump::spawn_after(Milliseconds(DUMP_RETRY_MS), message).ok();
     }
 }
+
+pub(crate) fn lockout_end(mut cx: lockout_end::Context) {
+    cx.shared
+        .conditions
+        .lock(|c: &mut Conditions| c.locked_out = false);
+}
@@ -0,0 +1,67 @@
+use core::convert::TryInto;
+use pike_enginecontrol::{
+    can_driver::CANDriver,
+    continuity::Continuity,
+    engine::Conditions,
+    telemetry::{NodeStatus, Telemetry},
+    StateEnum,
+};
+use rtic::{mutex_prelude::*, time::duration::Milliseconds};
+use state_governor::{state::State, Governor};
+
+use crate::{
+    app::telemetry_publish,
+    board::{PyroController, PYRO_CHANNEL_NAMES},
+    tasks::uptime_ms,
+};
+
+/// Current node status, `None` while the governor holds an unknown state.
+pub(crate) fn node_status(
+    governor: &mut impl Mutex<T = Governor<6>>,
+    conditions: &mut impl Mutex<T = Conditions>,
+    controller: &PyroController,
+) -> Option<NodeStatus> {
+    let state: StateEnum = governor
+        .lock(|g: &mut Governor<6>| g.get_current_state().id())
+        .try_into()
+        .ok()?;
+    let mut continuity = [Continuity::Unknown; 4];
+    for (continuity, channel) in continuity.iter_mut().zip(PYRO_CHANNEL_NAMES) {
+        *continuity = controller.continuity(channel);
+    }
+    Some(NodeStatus {
+        state,
+        pyro_state: controller.get_state(),
+        conditions: conditions.lock(|c: &mut Conditions| *c),
+        continuity,
+    })
+}
+
+/// Publishes the telemetry messages that are due and reschedules itself for the next one. Spawned
+/// out of cycle on every state change, which is then published right away.
+pub(crate) fn telemetry_publish(mut cx: telemetry_publish::Context) {
+    let telemetry: &mut Telemetry = cx.local.telemetry;
+    let timer: &mut Option<telemetry_publish::SpawnHandle> = cx.local.telemetry_timer;
+    let controller: &mut PyroController = cx.shared.pyro_controller;
+
+    let now = uptime_ms();
+    if let Some(status) = node_status(
+        &mut cx.shared.governor,
+        &mut cx.shared.conditions,
+        controller,
+    ) {
+        // Telemetry is not retried, a message that finds the transmit queue full is superseded by
+        // the next one
+        cx.shared.can_driver.lock(|driver: &mut CANDriver| {
+            let messages = telemetry.update(now, &status, &driver.health());
+            for message in messages.iter() {
+                driver.transmit(message);
+            }
+        });
+    }
+
+    if let Some(timer) = timer.take() {
+        timer.cancel().ok();
+    }
+    *timer = telemetry_publish::spawn_after(Milliseconds(telemetry.next_due_ms(now).max(1))).ok();
+}